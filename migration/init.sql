@@ -6,8 +6,10 @@ create table if not exists contacts (
   postal_code text,
   city text,
   email text not null unique,
-  unsubscribe_token text,
+  unsubscribe_token text unique,
   custom_fields text,
+  paused_until timestamp with time zone,
+  tracking_disabled boolean not null default 0,
   created_at timestamp with time zone default current_timestamp,
   updated_at timestamp with time zone default current_timestamp
 );
@@ -15,6 +17,7 @@ create table if not exists contact_lists (
   id text primary key,
   name text not null,
   type text check (type in ('automatic', 'manual')),
+  is_public boolean not null default 0,
   created_at timestamp with time zone default current_timestamp,
   updated_at timestamp with time zone default current_timestamp
 );
@@ -27,11 +30,32 @@ create table if not exists contact_list_members (
   foreign key (contact_id) references contacts (id) on delete cascade,
   foreign key (list_id) references contact_lists (id) on delete cascade
 );
+create table if not exists unsubscriptions (
+  id text primary key,
+  contact_id text not null,
+  list_id text not null,
+  created_at timestamp with time zone default current_timestamp,
+  foreign key (contact_id) references contacts (id) on delete cascade,
+  foreign key (list_id) references contact_lists (id) on delete cascade
+);
+create table if not exists suppressions (
+  email text primary key,
+  reason text not null,
+  source text not null check (source in ('manual', 'import', 'bounce', 'complaint')),
+  created_at timestamp with time zone default current_timestamp
+);
+-- addresses added to the recipients of every newsletter sent
+create table if not exists seed_addresses (
+  email text primary key,
+  created_at timestamp with time zone default current_timestamp
+);
 create table if not exists themes (
   id text primary key,
   name text not null,
   header text,
   footer text,
+  -- css inlined into the newsletters using the theme
+  stylesheet text,
   created_at timestamp with time zone default current_timestamp,
   updated_at timestamp with time zone default current_timestamp
 );
@@ -44,6 +68,14 @@ create table if not exists users (
   created_at timestamp with time zone default current_timestamp,
   updated_at timestamp with time zone default current_timestamp
 );
+create table if not exists sender_identities (
+  id text primary key,
+  from_name text not null,
+  from_email text not null,
+  reply_to text,
+  created_at timestamp with time zone default current_timestamp,
+  updated_at timestamp with time zone default current_timestamp
+);
 create table if not exists sendings (
   id text primary key,
   type text check (
@@ -53,19 +85,37 @@ create table if not exists sendings (
       'targeted_announcement'
     )
   ),
+  -- internal name, never shown to recipients
   name text not null,
+  subject text,
+  preheader text,
   send_date timestamp with time zone,
   status text check (
-    status in ('scheduled', 'sent', 'failed', 'draft')
+    status in (
+      'scheduled',
+      'sending',
+      'paused',
+      'sent',
+      'cancelled',
+      'failed',
+      'draft'
+    )
   ),
   content_html text,
   content_plain text,
+  -- source of markdown content, rendered into content_html and content_plain
+  content_markdown text,
   theme_id text,
+  -- null sends as the identity of the configuration
+  sender_identity_id text,
+  track_opens boolean not null default 0,
+  track_clicks boolean not null default 0,
   sent_at timestamp with time zone,
   sent_by text,
   created_at timestamp with time zone default current_timestamp,
   updated_at timestamp with time zone default current_timestamp,
-  foreign key (theme_id) references themes (id),
+  foreign key (theme_id) references themes (id) on delete set null,
+  foreign key (sender_identity_id) references sender_identities (id) on delete set null,
   foreign key (sent_by) references users (id)
 );
 create table if not exists sending_contact_lists (
@@ -76,4 +126,140 @@ create table if not exists sending_contact_lists (
   primary key (sending_id, contact_list_id),
   foreign key (sending_id) references sendings (id) on delete cascade,
   foreign key (contact_list_id) references contact_lists (id) on delete cascade
-);
\ No newline at end of file
+);
+create table if not exists deliveries (
+  id text primary key,
+  sending_id text not null,
+  contact_id text,
+  email text not null,
+  -- seed address copy, left out of the newsletter's statistics
+  is_seed boolean not null default 0,
+  -- lowercased domain of email, for the per-domain throttle limits
+  recipient_domain text,
+  status text check (
+    status in (
+      'queued',
+      'sent',
+      'failed',
+      'suppressed',
+      'bounced',
+      'cancelled'
+    )
+  ),
+  message_id text,
+  error text,
+  -- reply code of the last SMTP failure
+  smtp_code text,
+  attempts integer not null default 0,
+  -- SMTP relay of the last attempt
+  relay text,
+  sent_at timestamp with time zone,
+  -- set when a throttle limit or a temporary failure deferred the delivery
+  next_attempt_at timestamp with time zone,
+  created_at timestamp with time zone default current_timestamp,
+  updated_at timestamp with time zone default current_timestamp,
+  foreign key (sending_id) references sendings (id) on delete cascade,
+  foreign key (contact_id) references contacts (id) on delete set null
+);
+create index if not exists deliveries_sending_id on deliveries (sending_id);
+create index if not exists deliveries_message_id on deliveries (message_id);
+create index if not exists deliveries_sent_at on deliveries (sent_at);
+-- messages handed to a relay, counted by the throttle limits
+create index if not exists deliveries_relayed on deliveries (sent_at) where message_id is not null;
+create index if not exists deliveries_relayed_domain on deliveries (recipient_domain, sent_at) where message_id is not null;
+create table if not exists bounces (
+  id text primary key,
+  delivery_id text,
+  email text not null,
+  kind text check (kind in ('hard', 'soft')),
+  status_code text,
+  diagnostic text,
+  dsn_message_id text,
+  created_at timestamp with time zone default current_timestamp,
+  foreign key (delivery_id) references deliveries (id) on delete set null
+);
+create index if not exists bounces_email on bounces (email);
+create index if not exists bounces_delivery_id on bounces (delivery_id);
+-- abuse reports received from feedback loops
+create table if not exists complaints (
+  id text primary key,
+  delivery_id text,
+  email text not null,
+  feedback_type text not null,
+  user_agent text,
+  created_at timestamp with time zone default current_timestamp,
+  foreign key (delivery_id) references deliveries (id) on delete set null
+);
+create index if not exists complaints_delivery_id on complaints (delivery_id);
+create table if not exists open_events (
+  id text primary key,
+  delivery_id text not null,
+  contact_id text,
+  user_agent text,
+  created_at timestamp with time zone default current_timestamp,
+  foreign key (delivery_id) references deliveries (id) on delete cascade,
+  foreign key (contact_id) references contacts (id) on delete set null
+);
+create index if not exists open_events_delivery_id on open_events (delivery_id);
+create table if not exists click_events (
+  id text primary key,
+  delivery_id text not null,
+  contact_id text,
+  url text not null,
+  user_agent text,
+  created_at timestamp with time zone default current_timestamp,
+  foreign key (delivery_id) references deliveries (id) on delete cascade,
+  foreign key (contact_id) references contacts (id) on delete set null
+);
+create index if not exists click_events_delivery_id on click_events (delivery_id);
+create table if not exists webhooks (
+  id text primary key,
+  url text not null,
+  secret text not null,
+  events text not null,
+  is_active boolean not null default 1,
+  created_at timestamp with time zone default current_timestamp,
+  updated_at timestamp with time zone default current_timestamp
+);
+create table if not exists webhook_deliveries (
+  id text primary key,
+  webhook_id text not null,
+  event text not null,
+  payload text not null,
+  status text check (status in ('pending', 'delivered', 'failed')) default 'pending',
+  attempts integer not null default 0,
+  next_attempt_at timestamp with time zone,
+  response_status integer,
+  response_body text,
+  error text,
+  created_at timestamp with time zone default current_timestamp,
+  updated_at timestamp with time zone default current_timestamp,
+  foreign key (webhook_id) references webhooks (id) on delete cascade
+);
+create index if not exists webhook_deliveries_pending on webhook_deliveries (status, next_attempt_at);
+create table if not exists idempotency_keys (
+  key text primary key,
+  request_hash text not null,
+  status_code integer not null,
+  response text not null,
+  created_at timestamp with time zone default current_timestamp
+);
+create table if not exists audit_events (
+  id text primary key,
+  request_id text,
+  actor_type text check (actor_type in ('user', 'api_key', 'anonymous')),
+  actor_id text,
+  actor_email text,
+  action text not null,
+  method text not null,
+  route text not null,
+  target_type text,
+  target_id text,
+  before text,
+  after text,
+  changes text,
+  status_code integer not null,
+  created_at timestamp with time zone default current_timestamp
+);
+create index if not exists audit_events_created_at on audit_events (created_at);
+create index if not exists audit_events_target on audit_events (target_type, target_id);
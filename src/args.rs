@@ -1,6 +1,8 @@
 use clap::Parser;
 use std::path::PathBuf;
 
+use crate::config::config::DkimAlgorithm;
+
 #[derive(Parser, Debug)]
 #[command(name = "newsletter", about = "Application Newsletter", version = "1.0")]
 pub struct Args {
@@ -14,4 +16,25 @@ pub struct Args {
 
     #[arg(short = None, long = "init-db", action = clap::ArgAction::SetTrue, )]
     pub init_db: bool,
+
+    /// Writes a new DKIM private key to FILE and prints the DNS record to publish.
+    #[arg(
+        long = "generate-dkim-key",
+        value_name = "FILE",
+        requires = "dkim_domain"
+    )]
+    pub generate_dkim_key: Option<PathBuf>,
+
+    #[arg(long = "dkim-domain", value_name = "DOMAIN")]
+    pub dkim_domain: Option<String>,
+
+    #[arg(
+        long = "dkim-selector",
+        value_name = "SELECTOR",
+        default_value = "newsletter"
+    )]
+    pub dkim_selector: String,
+
+    #[arg(long = "dkim-algorithm", value_enum, default_value = "rsa")]
+    pub dkim_algorithm: DkimAlgorithm,
 }
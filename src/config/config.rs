@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -8,12 +9,25 @@ pub struct Config {
     pub database: DatabaseConfig,
     pub email: EmailConfig,
     pub site: SiteConfig,
+    #[serde(default)]
+    pub anti_abuse: AntiAbuseConfig,
+    #[serde(default)]
+    pub bounces: BouncesConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub contact_sync: ContactSyncConfig,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    #[serde(default)]
+    pub spam: SpamConfig,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ServerConfig {
     pub log_level: String,
     pub host: String,
+    pub secret_key: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -28,8 +42,15 @@ pub struct SqliteConfig {
 
 #[derive(Debug, Deserialize)]
 pub struct EmailConfig {
-    pub smtp: SmtpConfig,
+    /// Relay named `default`, with priority 0 and weight 1.
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub relays: Vec<RelayConfig>,
     pub identity: IdentityConfig,
+    #[serde(default)]
+    pub retry: EmailRetryConfig,
+    /// Outgoing messages are signed when set.
+    pub dkim: Option<DkimConfig>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -41,10 +62,86 @@ pub struct SmtpConfig {
     pub auth_password: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct RelayConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub smtp: SmtpConfig,
+    /// Relays with the lowest priority are used first, the others only when
+    /// they are all down.
+    #[serde(default)]
+    pub priority: u32,
+    /// Share of the traffic among the relays of the same priority.
+    #[serde(default = "default_relay_weight")]
+    pub weight: u32,
+}
+
+fn default_relay_weight() -> u32 {
+    1
+}
+
+impl EmailConfig {
+    /// `[email.smtp]` followed by the `[[email.relays]]`.
+    pub fn all_relays(&self) -> Vec<RelayConfig> {
+        let default = self.smtp.as_ref().map(|smtp| RelayConfig {
+            name: "default".to_string(),
+            smtp: smtp.clone(),
+            priority: 0,
+            weight: 1,
+        });
+        default
+            .into_iter()
+            .chain(self.relays.iter().cloned())
+            .collect()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct IdentityConfig {
     pub from_name: String,
     pub from_email: String,
+    /// Base envelope sender for VERP, e.g. `bounces@domain.tld` makes each
+    /// message go out as `bounces+<delivery-id>@domain.tld`.
+    pub verp_address: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct EmailRetryConfig {
+    /// SMTP attempts per delivery before a temporary failure is final.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt.
+    pub retry_base_secs: u64,
+    pub max_retry_delay_secs: u64,
+}
+
+impl Default for EmailRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            retry_base_secs: 300,
+            max_retry_delay_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimAlgorithm {
+    #[default]
+    Rsa,
+    Ed25519,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DkimConfig {
+    pub selector: String,
+    pub domain: String,
+    /// PKCS#1 PEM for RSA, the base64 encoded 32 byte seed for Ed25519, as
+    /// written by `--generate-dkim-key`.
+    pub private_key_path: PathBuf,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
 }
 
 #[derive(Debug, Deserialize)]
@@ -54,6 +151,120 @@ pub struct SiteConfig {
     pub site_url: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AntiAbuseConfig {
+    pub rate_limit_requests: u32,
+    pub rate_limit_window_secs: u64,
+    pub login_rate_limit_requests: u32,
+    pub login_rate_limit_window_secs: u64,
+    pub pow_enabled: bool,
+    pub pow_difficulty: u32,
+    pub disposable_domains_file: Option<PathBuf>,
+}
+
+impl Default for AntiAbuseConfig {
+    fn default() -> Self {
+        Self {
+            rate_limit_requests: 10,
+            rate_limit_window_secs: 60,
+            login_rate_limit_requests: 5,
+            login_rate_limit_window_secs: 300,
+            pow_enabled: false,
+            pow_difficulty: 16,
+            disposable_domains_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BouncesConfig {
+    pub maildir_path: Option<PathBuf>,
+    pub poll_interval_secs: u64,
+    pub ingest_token: Option<String>,
+    pub soft_bounce_threshold: u32,
+    pub soft_bounce_window_days: u32,
+}
+
+impl Default for BouncesConfig {
+    fn default() -> Self {
+        Self {
+            maildir_path: None,
+            poll_interval_secs: 60,
+            ingest_token: None,
+            soft_bounce_threshold: 3,
+            soft_bounce_window_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub poll_interval_secs: u64,
+    pub timeout_secs: u64,
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt.
+    pub retry_base_secs: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 5,
+            timeout_secs: 10,
+            max_attempts: 8,
+            retry_base_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ContactSyncConfig {
+    /// Shared secret signing incoming sync requests, the endpoint is
+    /// disabled when unset.
+    pub secret: Option<String>,
+    pub timestamp_tolerance_secs: i64,
+}
+
+impl Default for ContactSyncConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            timestamp_tolerance_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct ThrottleConfig {
+    /// Messages per second across all dispatches, unlimited when unset.
+    pub messages_per_second: Option<f64>,
+    /// Messages sent over any rolling hour, all newsletters together.
+    pub hourly_cap: Option<u32>,
+    /// Messages sent over any rolling day, all newsletters together.
+    pub daily_cap: Option<u32>,
+    /// Limits by recipient domain, e.g. `[throttle.domains."gmail.com"]`.
+    pub domains: HashMap<String, DomainThrottleConfig>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct DomainThrottleConfig {
+    pub messages_per_minute: Option<u32>,
+    pub messages_per_hour: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct SpamConfig {
+    /// Rules scoring newsletter content, nothing is scored when unset.
+    pub rules_file: Option<PathBuf>,
+}
+
 impl Config {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
         let content = fs::read_to_string(path)?;
@@ -69,8 +280,28 @@ impl Config {
         if self.server.host.trim().is_empty() {
             return Err("server.host is empty".into());
         }
-        if self.email.smtp.server_host.trim().is_empty() {
-            return Err("email.smtp.server_host is empty".into());
+        if self.server.secret_key.trim().is_empty() {
+            return Err("server.secret_key is empty".into());
+        }
+        let relays = self.email.all_relays();
+        if relays.is_empty() {
+            return Err("email.smtp or email.relays must be set".into());
+        }
+        for (index, relay) in relays.iter().enumerate() {
+            if relay.name.trim().is_empty() {
+                return Err("email.relays.name is empty".into());
+            }
+            if relays[..index].iter().any(|other| other.name == relay.name) {
+                return Err(format!("email relay '{}' is defined twice", relay.name).into());
+            }
+            if relay.smtp.server_host.trim().is_empty() {
+                return Err(format!("email relay '{}' server_host is empty", relay.name).into());
+            }
+            if relay.weight == 0 {
+                return Err(
+                    format!("email relay '{}' weight must be greater than 0", relay.name).into(),
+                );
+            }
         }
         // if self.email.smtp.auth_user.trim().is_empty() {
         //     return Err("email.smtp.auth_user is empty".into());
@@ -84,6 +315,30 @@ impl Config {
         if self.email.identity.from_email.trim().is_empty() {
             return Err("email.identity.from_email is empty".into());
         }
+        if let Some(verp_address) = &self.email.identity.verp_address {
+            if let Err(e) = verp_address.parse::<lettre::Address>() {
+                return Err(
+                    format!("email.identity.verp_address is not a valid address: {}", e).into(),
+                );
+            }
+            if verp_address.contains('+') {
+                return Err("email.identity.verp_address must not contain '+'".into());
+            }
+        }
+        if let Some(dkim) = &self.email.dkim {
+            if dkim.selector.trim().is_empty() {
+                return Err("email.dkim.selector is empty".into());
+            }
+            if dkim.domain.trim().is_empty() {
+                return Err("email.dkim.domain is empty".into());
+            }
+        }
+        if self.email.retry.max_attempts == 0 {
+            return Err("email.retry.max_attempts must be greater than 0".into());
+        }
+        if self.email.retry.max_retry_delay_secs > 7 * 24 * 60 * 60 {
+            return Err("email.retry.max_retry_delay_secs must be at most 7 days".into());
+        }
         if self.site.name.trim().is_empty() {
             return Err("site.name is empty".into());
         }
@@ -93,6 +348,52 @@ impl Config {
         if self.site.site_url.trim().is_empty() {
             return Err("site.site_url is empty".into());
         }
+        if self.anti_abuse.rate_limit_requests == 0 {
+            return Err("anti_abuse.rate_limit_requests must be greater than 0".into());
+        }
+        if self.anti_abuse.login_rate_limit_requests == 0 {
+            return Err("anti_abuse.login_rate_limit_requests must be greater than 0".into());
+        }
+        if self.anti_abuse.pow_difficulty > 32 {
+            return Err("anti_abuse.pow_difficulty must be at most 32".into());
+        }
+        if self.bounces.poll_interval_secs == 0 {
+            return Err("bounces.poll_interval_secs must be greater than 0".into());
+        }
+        if self.bounces.soft_bounce_threshold == 0 {
+            return Err("bounces.soft_bounce_threshold must be greater than 0".into());
+        }
+        if self.webhooks.poll_interval_secs == 0 {
+            return Err("webhooks.poll_interval_secs must be greater than 0".into());
+        }
+        if self.webhooks.max_attempts == 0 {
+            return Err("webhooks.max_attempts must be greater than 0".into());
+        }
+        if self
+            .throttle
+            .messages_per_second
+            .is_some_and(|rate| rate.is_nan() || rate <= 0.0)
+        {
+            return Err("throttle.messages_per_second must be greater than 0".into());
+        }
+        if self.throttle.hourly_cap == Some(0) || self.throttle.daily_cap == Some(0) {
+            return Err("throttle caps must be greater than 0".into());
+        }
+        for (domain, limits) in &self.throttle.domains {
+            if limits.messages_per_minute == Some(0) || limits.messages_per_hour == Some(0) {
+                return Err(
+                    format!("throttle.domains.{} limits must be greater than 0", domain).into(),
+                );
+            }
+        }
+        if self
+            .contact_sync
+            .secret
+            .as_ref()
+            .is_some_and(|secret| secret.trim().is_empty())
+        {
+            return Err("contact_sync.secret is empty".into());
+        }
         Ok(())
     }
 }
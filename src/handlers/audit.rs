@@ -0,0 +1,57 @@
+use crate::AppState;
+use crate::helpers::response::{response_err, response_success};
+use crate::models::audit::{AuditEvent, AuditQuery};
+use axum::extract::Query;
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use tracing::error;
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 500;
+
+#[tracing::instrument(skip(state))]
+pub async fn list_audit_events(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+    let events = sqlx::query_as::<_, AuditEvent>(
+        r#"
+        select id, request_id, actor_type, actor_id, actor_email, action, method, route,
+            target_type, target_id, before, after, changes, status_code, created_at
+        from audit_events
+        where (?1 is null or actor_id = ?1 or actor_email = ?1)
+          and (?2 is null or action = ?2)
+          and (?3 is null or target_type = ?3)
+          and (?4 is null or target_id = ?4)
+          and (?5 is null or request_id = ?5)
+          and (?6 is null or created_at >= ?6)
+          and (?7 is null or created_at < ?7)
+        order by created_at desc
+        limit ?8 offset ?9
+        "#,
+    )
+    .bind(&query.actor)
+    .bind(&query.action)
+    .bind(&query.target_type)
+    .bind(&query.target_id)
+    .bind(&query.request_id)
+    .bind(query.since)
+    .bind(query.until)
+    .bind(limit)
+    .bind(query.offset.unwrap_or(0))
+    .fetch_all(&state.db_pool)
+    .await;
+
+    match events {
+        Ok(events) => response_success(StatusCode::OK, events),
+        Err(e) => {
+            error!("Erreur de récupération du journal d'audit: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
@@ -8,13 +8,13 @@ use chrono::{Duration, Utc};
 use jsonwebtoken::{EncodingKey, Header, encode};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
-use validator::{Validate, ValidationErrors};
+use validator::Validate;
 
 use bcrypt::verify;
 
 use crate::{
     AppState,
-    helpers::response::ApiResponse,
+    helpers::response::{ApiResponse, extract_errors},
     models::types::{Claims, Session},
 };
 
@@ -128,21 +128,6 @@ pub async fn login(
     response_ok_with_cookie_jar(StatusCode::OK, (), updated_jar)
 }
 
-fn extract_errors(errors: ValidationErrors) -> String {
-    errors
-        .field_errors()
-        .iter()
-        .map(|(field, errs)| {
-            let messages: Vec<String> = errs
-                .iter()
-                .filter_map(|e| e.message.clone().map(|msg| msg.into_owned()))
-                .collect();
-            format!("{}: {}", field, messages.join(", "))
-        })
-        .collect::<Vec<String>>()
-        .join("; ")
-}
-
 fn response_ok_with_cookie_jar<T: Serialize>(
     status: StatusCode,
     data: T,
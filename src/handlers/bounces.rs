@@ -0,0 +1,91 @@
+use crate::config::config::BouncesConfig;
+use crate::helpers::audit::with_actor;
+use crate::helpers::bounces::{process_bounce, process_complaint};
+use crate::helpers::response::{response_err, response_success};
+use crate::helpers::signing::secret_matches;
+use crate::{APP_CONFIG, AppState};
+use axum::body::Bytes;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use tracing::error;
+
+/// Checks the `X-Ingest-Token` header against `bounces.ingest_token`.
+fn authorize(headers: &HeaderMap) -> Result<&'static BouncesConfig, (StatusCode, &'static str)> {
+    let config = &APP_CONFIG
+        .get()
+        .expect("Configuration not initialized")
+        .bounces;
+
+    let Some(expected_token) = config.ingest_token.as_deref() else {
+        return Err((StatusCode::NOT_FOUND, "Not found"));
+    };
+    let token = headers
+        .get("x-ingest-token")
+        .and_then(|value| value.to_str().ok());
+    if !token.is_some_and(|token| secret_matches(token, expected_token)) {
+        return Err((StatusCode::UNAUTHORIZED, "Jeton invalide"));
+    }
+    Ok(config)
+}
+
+/// POST /bounces
+///
+/// Receives a raw DSN message, e.g. piped from the MTA, authenticated with
+/// the `X-Ingest-Token` header matching `bounces.ingest_token`.
+#[tracing::instrument(skip(state, headers, body))]
+pub async fn ingest_bounce(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let config = match authorize(&headers) {
+        Ok(config) => config,
+        Err((status, message)) => return response_err(status, message.to_string()),
+    };
+
+    match process_bounce(&state.db_pool, config, &body).await {
+        Ok(Some(count)) => with_actor(response_success(StatusCode::OK, count), "bounce_ingest"),
+        Ok(None) => response_err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Aucun rapport de remise trouvé dans le message".to_string(),
+        ),
+        Err(e) => {
+            error!("Erreur de traitement du bounce: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+/// POST /complaints
+///
+/// Receives a raw ARF abuse report from a feedback loop, authenticated like
+/// `/bounces`.
+#[tracing::instrument(skip(state, headers, body))]
+pub async fn ingest_complaint(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err((status, message)) = authorize(&headers) {
+        return response_err(status, message.to_string());
+    }
+
+    match process_complaint(&state.db_pool, &body).await {
+        Ok(Some(email)) => with_actor(response_success(StatusCode::OK, email), "complaint_ingest"),
+        Ok(None) => response_err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Aucune plainte trouvée dans le message".to_string(),
+        ),
+        Err(e) => {
+            error!("Erreur de traitement de la plainte: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::AppState;
-use crate::helpers::response::{response_err, response_success};
+use crate::helpers::audit;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::helpers::signing::random_token;
 use crate::models::contact::{ContactListWithMembers, NewContactRequest};
 use crate::models::contact_lists::{ContactList, NewContactListRequest};
 use axum::Json;
@@ -10,11 +12,12 @@ use chrono::Utc;
 use sqlx::Row;
 use tracing::error;
 use uuid::Uuid;
+use validator::Validate;
 
 #[tracing::instrument(skip(state))]
 pub async fn list_contact_lists(State(state): State<AppState>) -> Response {
     let lists = match sqlx::query_as::<_, ContactList>(
-        "select id, name, type, created_at, updated_at from contact_lists",
+        "select id, name, type, is_public, created_at, updated_at from contact_lists",
     )
     .fetch_all(&state.db_pool)
     .await
@@ -45,15 +48,20 @@ pub async fn create_contact_list(
     }
 
     let id = Uuid::new_v4().to_string();
-    let result = sqlx::query("insert into contact_lists (id, name, type) values (?, ?, ?)")
-        .bind(&id)
-        .bind(&payload.name)
-        .bind(&list_type)
-        .execute(&state.db_pool)
-        .await;
+    let result =
+        sqlx::query("insert into contact_lists (id, name, type, is_public) values (?, ?, ?, ?)")
+            .bind(&id)
+            .bind(&payload.name)
+            .bind(&list_type)
+            .bind(payload.is_public.unwrap_or(false))
+            .execute(&state.db_pool)
+            .await;
 
     match result {
-        Ok(_) => response_success(StatusCode::CREATED, "Liste de contacts créée".to_string()),
+        Ok(_) => audit::with_target(
+            response_success(StatusCode::CREATED, "Liste de contacts créée".to_string()),
+            id,
+        ),
         Err(e) => {
             error!(
                 "Erreur lors de la création de la liste de contacts: {:?}",
@@ -73,7 +81,7 @@ pub async fn get_contact_list_by_id(
     Path(list_id): Path<String>,
 ) -> Response {
     let contact_list = sqlx::query_as::<_, ContactList>(
-        "SELECT id, name, type, created_at, updated_at FROM contact_lists WHERE id = ?",
+        "SELECT id, name, type, is_public, created_at, updated_at FROM contact_lists WHERE id = ?",
     )
     .bind(&list_id)
     .fetch_optional(&state.db_pool)
@@ -123,6 +131,7 @@ pub async fn get_contact_list_by_id(
         id: list.id,
         name: list.name,
         list_type: list.list_type,
+        is_public: list.is_public,
         created_at: list.created_at,
         updated_at: list.updated_at,
         members: member_ids,
@@ -137,11 +146,18 @@ pub async fn create_contact(
     Path(list_id): Path<String>,
     Json(payload): Json<NewContactRequest>,
 ) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
     let contact_id = Uuid::new_v4().to_string();
 
     if let Err(err) = sqlx::query(
-        "insert into contacts (id, first_name, last_name, address, postal_code, city, email, custom_fields, created_at, updated_at)
-         values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        "insert into contacts (id, first_name, last_name, address, postal_code, city, email, unsubscribe_token, custom_fields, tracking_disabled, created_at, updated_at)
+         values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(&contact_id)
     .bind(&payload.first_name)
@@ -150,7 +166,9 @@ pub async fn create_contact(
     .bind(&payload.postal_code)
     .bind(&payload.city)
     .bind(&payload.email)
-    .bind(&payload.custom_fields)
+    .bind(random_token(32))
+    .bind(payload.custom_fields_json())
+    .bind(payload.tracking_disabled.unwrap_or(false))
     .bind(Utc::now())
     .bind(Utc::now())
     .execute(&state.db_pool)
@@ -181,5 +199,8 @@ pub async fn create_contact(
         );
     };
 
-    response_success(StatusCode::CREATED, "Contact créé et ajouté à la liste")
+    audit::with_target(
+        response_success(StatusCode::CREATED, "Contact créé et ajouté à la liste"),
+        contact_id,
+    )
 }
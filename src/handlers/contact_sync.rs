@@ -0,0 +1,390 @@
+use crate::helpers::audit::{self, AuditTarget};
+use crate::helpers::response::{ApiResponse, extract_errors, response_err};
+use crate::helpers::signing::{random_token, verify_with};
+use crate::helpers::webhooks::{self, EVENT_SUBSCRIBED, EVENT_UNSUBSCRIBED};
+use crate::models::contact::{ContactSyncRequest, ContactSyncResponse};
+use crate::{APP_CONFIG, AppState};
+use axum::body::Bytes;
+use axum::http::{HeaderMap, header};
+use axum::response::{IntoResponse, Response};
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{Row, SqlitePool};
+use tracing::error;
+use uuid::Uuid;
+use validator::Validate;
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Checks `X-Sync-Signature: sha256=<hex>`, the HMAC-SHA256 of
+/// `{X-Sync-Timestamp}.{body}` keyed with `contact_sync.secret`.
+fn verify_signature(secret: &str, tolerance_secs: i64, headers: &HeaderMap, body: &[u8]) -> bool {
+    let Some(timestamp) = header_str(headers, "x-sync-timestamp") else {
+        return false;
+    };
+    let Ok(sent_at) = timestamp.parse::<i64>() else {
+        return false;
+    };
+    if (Utc::now().timestamp() - sent_at).abs() > tolerance_secs {
+        return false;
+    }
+    let Some(signature) =
+        header_str(headers, "x-sync-signature").and_then(|s| s.strip_prefix("sha256="))
+    else {
+        return false;
+    };
+    let signed = [timestamp.as_bytes(), b".", body].concat();
+    verify_with(secret.as_bytes(), &signed, signature)
+}
+
+fn stored_response(status_code: i64, body: String) -> Response {
+    let status = u16::try_from(status_code)
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .unwrap_or(StatusCode::OK);
+    (status, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// Response stored for `key`, or the 422 when the key was used for another
+/// request. `None` when the key was not used yet.
+async fn replay(pool: &SqlitePool, key: &str, request_hash: &str) -> Option<Response> {
+    let stored = sqlx::query(
+        "select request_hash, status_code, response from idempotency_keys where key = ?",
+    )
+    .bind(key)
+    .fetch_optional(pool)
+    .await;
+    match stored {
+        Ok(Some(row)) if row.get::<String, _>("request_hash") == request_hash => {
+            Some(stored_response(row.get("status_code"), row.get("response")))
+        }
+        Ok(Some(_)) => Some(response_err(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Clé d'idempotence déjà utilisée pour une autre requête".to_string(),
+        )),
+        Ok(None) => None,
+        Err(e) => {
+            error!("Erreur de lecture de la clé d'idempotence: {:?}", e);
+            Some(response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            ))
+        }
+    }
+}
+
+/// POST /contacts/sync
+///
+/// Upserts a contact by email from another system, merging its
+/// `custom_fields` and adding/removing list memberships. Requests are signed
+/// with `contact_sync.secret`, and an `Idempotency-Key` header makes retries
+/// return the first response instead of applying the change again.
+#[tracing::instrument(skip(state, headers, body))]
+pub async fn sync_contact(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let config = &APP_CONFIG
+        .get()
+        .expect("Configuration not initialized")
+        .contact_sync;
+
+    let Some(secret) = config.secret.as_deref() else {
+        return response_err(StatusCode::NOT_FOUND, "Not found".to_string());
+    };
+    if !verify_signature(secret, config.timestamp_tolerance_secs, &headers, &body) {
+        return response_err(StatusCode::UNAUTHORIZED, "Signature invalide".to_string());
+    }
+
+    audit::with_actor(apply_sync(&state, &headers, &body).await, "contact_sync")
+}
+
+/// Applies a sync request whose signature has been verified.
+async fn apply_sync(state: &AppState, headers: &HeaderMap, body: &[u8]) -> Response {
+    let idempotency_key = header_str(headers, "idempotency-key").map(str::trim);
+    if idempotency_key.is_some_and(|key| key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN) {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            "Clé d'idempotence invalide".to_string(),
+        );
+    }
+    let request_hash = hex::encode(Sha256::digest(body));
+
+    if let Some(key) = idempotency_key
+        && let Some(response) = replay(&state.db_pool, key, &request_hash).await
+    {
+        return response;
+    }
+
+    let payload: ContactSyncRequest = match serde_json::from_slice(body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return response_err(StatusCode::BAD_REQUEST, format!("JSON invalide: {}", e));
+        }
+    };
+    if let Err(validation_errors) = payload.contact.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let add_lists = payload.add_lists.clone().unwrap_or_default();
+    let remove_lists = payload.remove_lists.clone().unwrap_or_default();
+    for list_id in add_lists.iter().chain(&remove_lists) {
+        match sqlx::query("select 1 from contact_lists where id = ?")
+            .bind(list_id)
+            .fetch_optional(&state.db_pool)
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return response_err(
+                    StatusCode::BAD_REQUEST,
+                    format!("Liste de contacts {} inconnue", list_id),
+                );
+            }
+            Err(e) => {
+                error!("Erreur de récupération de la liste {}: {:?}", list_id, e);
+                return response_err(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Erreur de base de données".to_string(),
+                );
+            }
+        }
+    }
+
+    let contact = &payload.contact;
+    let email = contact.email.trim().to_lowercase();
+
+    let before = match audit::snapshot(&state.db_pool, "contacts", "lower(email)", &email).await {
+        Ok(before) => before,
+        Err(e) => {
+            error!("Erreur de récupération du contact {}: {:?}", email, e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Erreur d'ouverture de transaction: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    // The key is claimed before anything is changed: a concurrent retry
+    // waits for this transaction, then finds the key taken and replays the
+    // stored response.
+    if let Some(key) = idempotency_key {
+        let claimed = sqlx::query(
+            "insert into idempotency_keys (key, request_hash, status_code, response, created_at)
+             values (?, ?, 0, '', ?)
+             on conflict (key) do nothing",
+        )
+        .bind(key)
+        .bind(&request_hash)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await;
+        match claimed {
+            Ok(claimed) if claimed.rows_affected() > 0 => {}
+            Ok(_) => {
+                if let Err(e) = tx.rollback().await {
+                    error!("Erreur lors de l'annulation de la transaction: {:?}", e);
+                }
+                return replay(&state.db_pool, key, &request_hash)
+                    .await
+                    .unwrap_or_else(|| {
+                        response_err(
+                            StatusCode::CONFLICT,
+                            "Requête déjà en cours pour cette clé d'idempotence".to_string(),
+                        )
+                    });
+            }
+            Err(e) => {
+                error!("Erreur d'enregistrement de la clé d'idempotence: {:?}", e);
+                return response_err(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Erreur de base de données".to_string(),
+                );
+            }
+        }
+    }
+
+    let result: Result<(ContactSyncResponse, String), sqlx::Error> = async {
+        let existing = sqlx::query("select id from contacts where lower(email) = ?")
+            .bind(&email)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(|row| row.get::<String, _>("id"));
+        let created = existing.is_none();
+
+        let contact_id = match existing {
+            Some(contact_id) => {
+                sqlx::query(
+                    "update contacts
+                     set first_name = coalesce(?, first_name),
+                         last_name = coalesce(?, last_name),
+                         address = coalesce(?, address),
+                         postal_code = coalesce(?, postal_code),
+                         city = coalesce(?, city),
+                         custom_fields = case when ? is null then custom_fields
+                             else json_patch(coalesce(custom_fields, '{}'), ?) end,
+                         tracking_disabled = coalesce(?, tracking_disabled),
+                         updated_at = ?
+                     where id = ?",
+                )
+                .bind(&contact.first_name)
+                .bind(&contact.last_name)
+                .bind(&contact.address)
+                .bind(&contact.postal_code)
+                .bind(&contact.city)
+                .bind(contact.custom_fields_json())
+                .bind(contact.custom_fields_json())
+                .bind(contact.tracking_disabled)
+                .bind(Utc::now())
+                .bind(&contact_id)
+                .execute(&mut *tx)
+                .await?;
+                contact_id
+            }
+            None => {
+                let contact_id = Uuid::new_v4().to_string();
+                sqlx::query(
+                    "insert into contacts (id, first_name, last_name, address, postal_code, city, email, unsubscribe_token, custom_fields, tracking_disabled, created_at, updated_at)
+                     values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&contact_id)
+                .bind(&contact.first_name)
+                .bind(&contact.last_name)
+                .bind(&contact.address)
+                .bind(&contact.postal_code)
+                .bind(&contact.city)
+                .bind(&email)
+                .bind(random_token(32))
+                .bind(contact.custom_fields_json())
+                .bind(contact.tracking_disabled.unwrap_or(false))
+                .bind(Utc::now())
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await?;
+                contact_id
+            }
+        };
+
+        let mut added_lists = Vec::new();
+        for list_id in &add_lists {
+            let added = sqlx::query(
+                "insert or ignore into contact_list_members (contact_id, list_id) values (?, ?)",
+            )
+            .bind(&contact_id)
+            .bind(list_id)
+            .execute(&mut *tx)
+            .await?;
+            if added.rows_affected() > 0 {
+                added_lists.push(list_id.clone());
+            }
+        }
+
+        let mut removed_lists = Vec::new();
+        for list_id in &remove_lists {
+            let removed =
+                sqlx::query("delete from contact_list_members where contact_id = ? and list_id = ?")
+                    .bind(&contact_id)
+                    .bind(list_id)
+                    .execute(&mut *tx)
+                    .await?;
+            if removed.rows_affected() > 0 {
+                sqlx::query(
+                    "insert into unsubscriptions (id, contact_id, list_id, created_at) values (?, ?, ?, ?)",
+                )
+                .bind(Uuid::new_v4().to_string())
+                .bind(&contact_id)
+                .bind(list_id)
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await?;
+                removed_lists.push(list_id.clone());
+            }
+        }
+
+        let response = ContactSyncResponse {
+            contact_id,
+            created,
+            added_lists,
+            removed_lists,
+        };
+        let body = serde_json::to_string(&ApiResponse::success(&response))
+            .expect("Sync response is serializable");
+
+        if let Some(key) = idempotency_key {
+            sqlx::query("update idempotency_keys set status_code = ?, response = ? where key = ?")
+                .bind(StatusCode::OK.as_u16())
+                .bind(&body)
+                .bind(key)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        Ok((response, body))
+    }
+    .await;
+
+    let (response, body) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Erreur lors de la synchronisation du contact: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de la synchronisation du contact".to_string(),
+            );
+        }
+    };
+    if let Err(e) = tx.commit().await {
+        error!("Erreur lors de la validation de la transaction: {:?}", e);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de la synchronisation du contact".to_string(),
+        );
+    }
+
+    let events = response
+        .added_lists
+        .iter()
+        .map(|list_id| (EVENT_SUBSCRIBED, list_id))
+        .chain(
+            response
+                .removed_lists
+                .iter()
+                .map(|list_id| (EVENT_UNSUBSCRIBED, list_id)),
+        );
+    for (event, list_id) in events {
+        webhooks::emit(
+            &state.db_pool,
+            event,
+            json!({ "contact_id": response.contact_id, "email": email, "list_id": list_id }),
+        )
+        .await;
+    }
+
+    let mut http_response = stored_response(StatusCode::OK.as_u16().into(), body);
+    http_response.extensions_mut().insert(AuditTarget {
+        id: response.contact_id,
+        before,
+    });
+    http_response
+}
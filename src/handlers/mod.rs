@@ -1,3 +1,16 @@
+pub mod audit;
 pub mod auth;
+pub mod bounces;
 pub mod contact_lists;
+pub mod contact_sync;
 pub mod newsletters;
+pub mod preferences;
+pub mod relays;
+pub mod seed_addresses;
+pub mod sender_identities;
+pub mod stats;
+pub mod subscriptions;
+pub mod suppressions;
+pub mod themes;
+pub mod tracking;
+pub mod webhooks;
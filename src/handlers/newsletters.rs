@@ -1,28 +1,59 @@
+use crate::APP_CONFIG;
 use crate::AppState;
+use crate::handlers::sender_identities::find_sender_identity;
+use crate::handlers::themes::find_theme;
+use crate::helpers::audit;
 use crate::helpers::email::Email;
+use crate::helpers::preflight;
+use crate::helpers::render;
 use crate::helpers::response::{response_err, response_success};
+use crate::helpers::sanitize;
+use crate::helpers::sender::{self, Personalized};
+use crate::helpers::spam::SpamRules;
+use crate::helpers::throttle::recipient_domain;
 use crate::models::contact::ContactEmail;
 use crate::models::newsletters::{
-    NewsletterForSend, NewsletterRaw, NewsletterRequest, NewsletterWithLists,
+    NewsletterForSend, NewsletterRaw, NewsletterRequest, NewsletterWithLists, PreviewQuery,
+    QueuedDelivery, SendProgress, TestSendRequest, TestSendResult,
 };
 use crate::models::types::Session;
-use axum::extract::Path;
+use axum::extract::{Path, Query};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::{Extension, Json};
 use axum::{extract::State, http::StatusCode, response::Response};
 use chrono::{NaiveDateTime, TimeZone, Utc};
+use sqlx::SqlitePool;
 use std::collections::HashSet;
-use tracing::{error, info};
+use std::pin::Pin;
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{error, warn};
 use uuid::Uuid;
+use validator::ValidateEmail;
+
+type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
+/// Most addresses a test can be sent to at once.
+const MAX_TEST_ADDRESSES: usize = 10;
 
 #[tracing::instrument(skip(state))]
 pub async fn get_newsletters(State(state): State<AppState>) -> Response {
     let query = r#"
         select s.id,
             s.name,
+            s.subject,
+            s.preheader,
             s.send_date,
             s.status,
             s.content_html,
             s.content_plain,
+            s.content_markdown,
+            s.track_opens,
+            s.track_clicks,
+            s.theme_id,
+            s.sender_identity_id,
             s.sent_at,
             u.email as sent_by,
             s.created_at,
@@ -61,10 +92,17 @@ pub async fn get_newsletters(State(state): State<AppState>) -> Response {
             NewsletterWithLists {
                 id: raw.id,
                 name: raw.name,
+                subject: raw.subject,
+                preheader: raw.preheader,
                 send_date: raw.send_date,
                 status: raw.status,
                 content_html: raw.content_html,
                 content_plain: raw.content_plain,
+                content_markdown: raw.content_markdown,
+                track_opens: raw.track_opens,
+                track_clicks: raw.track_clicks,
+                theme_id: raw.theme_id,
+                sender_identity_id: raw.sender_identity_id,
                 sent_at: raw.sent_at,
                 sent_by: raw.sent_by,
                 created_at: raw.created_at,
@@ -107,31 +145,109 @@ pub async fn create_newsletter(
         return response_err(StatusCode::BAD_REQUEST, "Action invalide".to_string());
     };
 
-    let (content_plain, content_html) = if payload.content_type.to_lowercase() == "text" {
-        (Some(payload.content), None)
-    } else if payload.content_type.to_lowercase() == "html" {
-        (None, Some(payload.content))
+    let content_type = payload.content_type.to_lowercase();
+    let (content_plain, content_html, content_markdown) = if content_type == "text" {
+        (Some(payload.content), None, None)
+    } else if content_type == "html" {
+        (None, Some(payload.content), None)
+    } else if content_type == "markdown" {
+        (
+            Some(render::markdown_to_text(&payload.content)),
+            Some(render::markdown_to_html(&payload.content)),
+            Some(payload.content),
+        )
     } else {
         return response_err(
             StatusCode::BAD_REQUEST,
             "Type de contenu invalide".to_string(),
         );
     };
+    let (content_html, removed) = match content_html {
+        Some(html) => match sanitize::sanitize_html(&html) {
+            Ok(sanitized) => (Some(sanitized.html), sanitized.removed),
+            Err(e) => {
+                error!("Erreur lors du nettoyage du HTML: {:?}", e);
+                return response_err(
+                    StatusCode::BAD_REQUEST,
+                    "Contenu HTML illisible".to_string(),
+                );
+            }
+        },
+        None => (None, Vec::new()),
+    };
+
+    let subject = payload
+        .subject
+        .as_deref()
+        .map(str::trim)
+        .filter(|subject| !subject.is_empty());
+    if payload.action == "scheduled" && subject.is_none() {
+        return response_err(StatusCode::BAD_REQUEST, "Objet manquant".to_string());
+    }
+    let preheader = payload
+        .preheader
+        .as_deref()
+        .map(str::trim)
+        .filter(|preheader| !preheader.is_empty());
+
+    if let Some(theme_id) = &payload.theme_id {
+        match find_theme(&state.db_pool, theme_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return response_err(StatusCode::BAD_REQUEST, "Thème inconnu".to_string());
+            }
+            Err(e) => {
+                error!("Erreur de récupération du thème {}: {:?}", theme_id, e);
+                return response_err(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Erreur de base de données".to_string(),
+                );
+            }
+        }
+    }
+    if let Some(identity_id) = &payload.sender_identity_id {
+        match find_sender_identity(&state.db_pool, identity_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return response_err(
+                    StatusCode::BAD_REQUEST,
+                    "Identité d'expéditeur inconnue".to_string(),
+                );
+            }
+            Err(e) => {
+                error!(
+                    "Erreur de récupération de l'identité d'expéditeur {}: {:?}",
+                    identity_id, e
+                );
+                return response_err(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Erreur de base de données".to_string(),
+                );
+            }
+        }
+    }
 
     let id = Uuid::new_v4().to_string();
 
     let result = sqlx::query(
-        "insert into sendings (id, type, name, send_date, sent_by, status, content_html, content_plain, sent_at, theme_id)
-         values (?, 'newsletter', ?, ?, ?, ?, ?, ?, ?, NULL);"
+        "insert into sendings (id, type, name, subject, preheader, send_date, sent_by, status, content_html, content_plain, content_markdown, track_opens, track_clicks, sent_at, theme_id, sender_identity_id)
+         values (?, 'newsletter', ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);"
     )
     .bind(&id)
     .bind(&payload.name)
+    .bind(subject)
+    .bind(preheader)
     .bind(send_date)
     .bind(session.user_id)
     .bind(status)
     .bind(content_html)
     .bind(content_plain)
+    .bind(content_markdown)
+    .bind(payload.track_opens.unwrap_or(false))
+    .bind(payload.track_clicks.unwrap_or(false))
     .bind(sent_at)
+    .bind(&payload.theme_id)
+    .bind(&payload.sender_identity_id)
     .execute(&state.db_pool)
     .await;
 
@@ -166,7 +282,13 @@ pub async fn create_newsletter(
         }
     }
 
-    response_success(StatusCode::CREATED, "Newsletter créée".to_string())
+    audit::with_target(
+        response_success(
+            StatusCode::CREATED,
+            serde_json::json!({ "message": "Newsletter créée", "removed": removed }),
+        ),
+        id,
+    )
 }
 
 #[tracing::instrument(skip(state), level = "debug")]
@@ -177,9 +299,13 @@ pub async fn send_newsletter(
 ) -> Response {
     let newsletter: NewsletterForSend = match sqlx::query_as(
         r#"
-        SELECT id, name, content_html, content_plain
-        FROM sendings
-        WHERE id = ? AND type = 'newsletter' AND status = 'scheduled'
+        SELECT s.id, coalesce(s.subject, '') as subject, s.preheader, s.content_html, s.content_plain, s.track_opens, s.track_clicks,
+            si.from_name, si.from_email, si.reply_to,
+            t.header as theme_header, t.footer as theme_footer, t.stylesheet as theme_stylesheet
+        FROM sendings s
+        LEFT JOIN sender_identities si ON si.id = s.sender_identity_id
+        LEFT JOIN themes t ON t.id = s.theme_id
+        WHERE s.id = ? AND s.type = 'newsletter' AND s.status = 'scheduled'
         "#,
     )
     .bind(newsletter_id.as_str())
@@ -199,16 +325,46 @@ pub async fn send_newsletter(
             );
         }
     };
+    if sender::subscribe(&newsletter.id).is_some() {
+        return response_err(StatusCode::CONFLICT, "Envoi déjà en cours".to_string());
+    }
+    let preflight = match preflight::check(&state.db_pool, &newsletter).await {
+        Ok(preflight) => preflight,
+        Err(e) => {
+            error!(
+                "Erreur de vérification de la newsletter {}: {:?}",
+                newsletter.id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+    if !preflight.errors.is_empty() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Envoi impossible: {}", preflight.errors.join("; ")),
+        );
+    }
+    // Warnings do not prevent the send, they are listed by the preflight
+    // route.
+    for warning in &preflight.warnings {
+        warn!("Newsletter {}: {}", newsletter.id, warning);
+    }
+
     let contacts: Vec<ContactEmail> = match sqlx::query_as(
         r#"
-        SELECT c.email
+        SELECT DISTINCT c.id, c.email, c.tracking_disabled
         FROM contacts c
         JOIN contact_list_members clm ON c.id = clm.contact_id
         JOIN sending_contact_lists scl ON clm.list_id = scl.contact_list_id
         WHERE scl.sending_id = ?
+          AND (c.paused_until IS NULL OR c.paused_until <= ?)
         "#,
     )
     .bind(newsletter_id.as_str())
+    .bind(Utc::now())
     .fetch_all(&state.db_pool)
     .await
     {
@@ -226,26 +382,601 @@ pub async fn send_newsletter(
         }
     };
 
+    // Contacts that already have a delivery (from an interrupted send) are not
+    // queued twice, their pending deliveries are picked up again.
+    let queued: Result<(), sqlx::Error> = async {
+        let mut tx = state.db_pool.begin().await?;
+        for contact in &contacts {
+            sqlx::query(
+                "insert into deliveries (id, sending_id, contact_id, email, recipient_domain, status, created_at, updated_at)
+                 select ?, ?, ?, ?, ?, 'queued', ?, ?
+                 where not exists (select 1 from deliveries where sending_id = ? and contact_id = ?)",
+            )
+            .bind(Uuid::new_v4().to_string())
+            .bind(&newsletter.id)
+            .bind(&contact.id)
+            .bind(&contact.email)
+            .bind(recipient_domain(&contact.email))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(&newsletter.id)
+            .bind(&contact.id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        // Seed addresses get the newsletter too, unless they are already
+        // among the recipients.
+        let seeds: Vec<(String,)> = sqlx::query_as("select email from seed_addresses")
+            .fetch_all(&mut *tx)
+            .await?;
+        for (email,) in &seeds {
+            sqlx::query(
+                "insert into deliveries (id, sending_id, contact_id, email, is_seed, recipient_domain, status, created_at, updated_at)
+                 select ?, ?, null, ?, 1, ?, 'queued', ?, ?
+                 where not exists (select 1 from deliveries where sending_id = ? and lower(email) = ?)",
+            )
+            .bind(Uuid::new_v4().to_string())
+            .bind(&newsletter.id)
+            .bind(email)
+            .bind(recipient_domain(email))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(&newsletter.id)
+            .bind(email)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+    .await;
+    if let Err(e) = queued {
+        error!(
+            "Erreur lors de la mise en file des remises de la newsletter {}: {:?}",
+            newsletter.id, e
+        );
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur de base de données".into(),
+        );
+    }
+
+    let progress = match sender::load_progress(&state.db_pool, &newsletter.id).await {
+        Ok(progress) => progress,
+        Err(e) => {
+            error!(
+                "Erreur de lecture de la progression de la newsletter {}: {:?}",
+                newsletter.id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+    let queued = progress.queued;
+    if !sender::start(state.db_pool.clone(), newsletter, progress) {
+        return response_err(StatusCode::CONFLICT, "Envoi déjà en cours".to_string());
+    }
+
+    response_success(
+        StatusCode::ACCEPTED,
+        format!("Envoi démarré: {} destinataires en file d'attente", queued),
+    )
+}
+
+/// GET /newsletters/{id}/progress
+///
+/// Server-sent events with the counters of the newsletter's dispatch, one
+/// `progress` event per change until the dispatch ends. When nothing is
+/// running, a single event with the stored counters is sent.
+#[tracing::instrument(skip(state))]
+pub async fn newsletter_progress(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+) -> Response {
+    let progress_events = |stream: BoxStream<SendProgress>| {
+        Sse::new(stream.map(|progress| Event::default().event("progress").json_data(progress)))
+            .keep_alive(KeepAlive::default())
+            .into_response()
+    };
+
+    if let Some(receiver) = sender::subscribe(&newsletter_id) {
+        return progress_events(Box::pin(WatchStream::new(receiver)));
+    }
+
+    let exists = sqlx::query("select 1 from sendings where id = ? and type = 'newsletter'")
+        .bind(&newsletter_id)
+        .fetch_optional(&state.db_pool)
+        .await;
+    match exists {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into());
+        }
+        Err(e) => {
+            error!(
+                "Erreur de récupération de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    }
+
+    match sender::load_progress(&state.db_pool, &newsletter_id).await {
+        Ok(progress) => progress_events(Box::pin(tokio_stream::once(progress))),
+        Err(e) => {
+            error!(
+                "Erreur de lecture de la progression de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            )
+        }
+    }
+}
+
+/// A newsletter with its sender identity and theme, whatever its status.
+pub async fn find_newsletter_for_send(
+    pool: &SqlitePool,
+    newsletter_id: &str,
+) -> Result<Option<NewsletterForSend>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        select s.id, coalesce(s.subject, '') as subject, s.preheader, s.content_html, s.content_plain, s.track_opens, s.track_clicks,
+            si.from_name, si.from_email, si.reply_to,
+            t.header as theme_header, t.footer as theme_footer, t.stylesheet as theme_stylesheet
+        from sendings s
+        left join sender_identities si on si.id = s.sender_identity_id
+        left join themes t on t.id = s.theme_id
+        where s.id = ? and s.type = 'newsletter'
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_optional(pool)
+    .await
+}
+
+async fn sending_status(state: &AppState, newsletter_id: &str) -> Result<Option<String>, Response> {
+    sqlx::query_as::<_, (String,)>(
+        "select status from sendings where id = ? and type = 'newsletter'",
+    )
+    .bind(newsletter_id)
+    .fetch_optional(&state.db_pool)
+    .await
+    .map(|row| row.map(|(status,)| status))
+    .map_err(|e| {
+        error!(
+            "Erreur de récupération de la newsletter {}: {:?}",
+            newsletter_id, e
+        );
+        response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur de base de données".into(),
+        )
+    })
+}
+
+/// POST /newsletters/{id}/pause
+///
+/// The dispatch stops before its next delivery; the one being sent finishes.
+#[tracing::instrument(skip(state))]
+pub async fn pause_newsletter(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+) -> Response {
+    match sending_status(&state, &newsletter_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(response) => return response,
+    }
+
+    if sender::send_control(&newsletter_id, sender::Control::Pause) {
+        response_success(StatusCode::OK, "Envoi mis en pause".to_string())
+    } else {
+        response_err(StatusCode::CONFLICT, "Aucun envoi en cours".to_string())
+    }
+}
+
+/// POST /newsletters/{id}/resume
+///
+/// Resumes a paused dispatch. A newsletter left `paused` or `sending` by a
+/// restart gets a new dispatch for its remaining queued deliveries.
+#[tracing::instrument(skip(state))]
+pub async fn resume_newsletter(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+) -> Response {
+    let status = match sending_status(&state, &newsletter_id).await {
+        Ok(Some(status)) => status,
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(response) => return response,
+    };
+
+    if sender::send_control(&newsletter_id, sender::Control::Run) {
+        return response_success(StatusCode::OK, "Envoi repris".to_string());
+    }
+    if sender::subscribe(&newsletter_id).is_some() {
+        return response_err(StatusCode::CONFLICT, "Envoi déjà en cours".to_string());
+    }
+    if status != "paused" && status != "sending" {
+        return response_err(StatusCode::CONFLICT, "Aucun envoi en pause".to_string());
+    }
+
+    let newsletter = match find_newsletter_for_send(&state.db_pool, &newsletter_id).await {
+        Ok(Some(newsletter)) => newsletter,
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(e) => {
+            error!(
+                "Erreur de récupération de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+    let progress = match sender::load_progress(&state.db_pool, &newsletter_id).await {
+        Ok(progress) => progress,
+        Err(e) => {
+            error!(
+                "Erreur de lecture de la progression de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+    let queued = progress.queued;
+    if !sender::start(state.db_pool.clone(), newsletter, progress) {
+        return response_err(StatusCode::CONFLICT, "Envoi déjà en cours".to_string());
+    }
+
+    response_success(
+        StatusCode::ACCEPTED,
+        format!("Envoi repris: {} destinataires en file d'attente", queued),
+    )
+}
+
+/// POST /newsletters/{id}/cancel
+///
+/// Deliveries still queued are marked `cancelled`, those already sent are kept.
+#[tracing::instrument(skip(state))]
+pub async fn cancel_newsletter(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+) -> Response {
+    let status = match sending_status(&state, &newsletter_id).await {
+        Ok(Some(status)) => status,
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(response) => return response,
+    };
+
+    if sender::send_control(&newsletter_id, sender::Control::Cancel) {
+        return response_success(StatusCode::OK, "Envoi annulé".to_string());
+    }
+    if sender::subscribe(&newsletter_id).is_some() || (status != "paused" && status != "sending") {
+        return response_err(StatusCode::CONFLICT, "Aucun envoi en cours".to_string());
+    }
+
+    match sender::cancel_queued(&state.db_pool, &newsletter_id).await {
+        Ok(()) => response_success(StatusCode::OK, "Envoi annulé".to_string()),
+        Err(e) => {
+            error!(
+                "Erreur d'annulation de l'envoi de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            )
+        }
+    }
+}
+
+/// GET /newsletters/{id}/preview?contact_id=...&format=html|text|eml
+///
+/// The message the contact would receive, built as the sender does. `eml`
+/// returns the whole message with its headers. Tracking uses a delivery id
+/// of its own, so opens and clicks of a preview are not recorded.
+#[tracing::instrument(skip(state))]
+pub async fn preview_newsletter(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+    Query(query): Query<PreviewQuery>,
+) -> Response {
+    let format = query.format.as_deref().unwrap_or("html");
+    if !["html", "text", "eml"].contains(&format) {
+        return response_err(StatusCode::BAD_REQUEST, "Format invalide".to_string());
+    }
+    let Some(contact_id) = query.contact_id else {
+        return response_err(StatusCode::BAD_REQUEST, "Contact manquant".to_string());
+    };
+
+    let newsletter = match find_newsletter_for_send(&state.db_pool, &newsletter_id).await {
+        Ok(Some(newsletter)) => newsletter,
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(e) => {
+            error!(
+                "Erreur de récupération de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+    let delivery = match sqlx::query_as::<_, QueuedDelivery>(
+        "select ? as id, email, first_name, last_name, address, postal_code, city, custom_fields,
+            unsubscribe_token, tracking_disabled, 0 as attempts
+         from contacts where id = ?",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&contact_id)
+    .fetch_optional(&state.db_pool)
+    .await
+    {
+        Ok(Some(delivery)) => delivery,
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Contact non trouvé".into()),
+        Err(e) => {
+            error!("Erreur de récupération du contact {}: {:?}", contact_id, e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+
+    let rendered = render::render_body(&newsletter);
+    let Personalized {
+        subject,
+        body,
+        text,
+    } = sender::personalize(&newsletter, &delivery, &rendered);
+    match format {
+        "html" => ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response(),
+        "text" => (
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            text.unwrap_or(body),
+        )
+            .into_response(),
+        _ => {
+            let email = Email::get();
+            let sender = email.sender(
+                newsletter.from_name.as_deref(),
+                newsletter.from_email.as_deref(),
+                newsletter.reply_to.as_deref(),
+            );
+            let message = email.build_message(
+                &sender,
+                &delivery.id,
+                &delivery.email,
+                &subject,
+                &body,
+                text.as_deref(),
+            );
+            match message {
+                Ok(message) => (
+                    [(header::CONTENT_TYPE, "message/rfc822")],
+                    message.formatted(),
+                )
+                    .into_response(),
+                Err(e) => response_err(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+            }
+        }
+    }
+}
+
+/// POST /newsletters/{id}/test
+///
+/// Sends the newsletter, whatever its status, to `emails` or the site's
+/// admins with a `[TEST]` subject prefix. Merge tags are filled from the
+/// contact with the same address when there is one. Test messages are not
+/// recorded as deliveries.
+#[tracing::instrument(skip(state))]
+pub async fn test_newsletter(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+    Json(payload): Json<TestSendRequest>,
+) -> Response {
+    let emails = if payload.emails.is_empty() {
+        APP_CONFIG
+            .get()
+            .expect("Configuration not initialized")
+            .site
+            .admin_emails
+            .clone()
+    } else {
+        payload.emails
+    };
+    if emails.is_empty() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            "Aucune adresse de test ni administrateur configuré".to_string(),
+        );
+    }
+    if emails.len() > MAX_TEST_ADDRESSES {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("{} adresses de test au maximum", MAX_TEST_ADDRESSES),
+        );
+    }
+    if let Some(invalid) = emails.iter().find(|email| !email.validate_email()) {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Adresse e-mail invalide: {}", invalid),
+        );
+    }
+
+    let newsletter = match find_newsletter_for_send(&state.db_pool, &newsletter_id).await {
+        Ok(Some(newsletter)) => newsletter,
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(e) => {
+            error!(
+                "Erreur de récupération de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+    let rendered = render::render_body(&newsletter);
     let email_helper = Email::get();
-    let email_body = newsletter
-        .content_html
-        .unwrap_or_else(|| newsletter.content_plain.unwrap_or_default());
-    let (sent, failed) =
-        contacts.iter().fold((0, 0), |(sent, failed), contact| {
-            match email_helper.send_email(&contact.email, &newsletter.name, &email_body) {
-                Ok(_) => {
-                    info!("Email envoyé à {}", contact.email);
-                    (sent + 1, failed)
-                }
-                Err(e) => {
-                    error!("Erreur d'envoi à {}: {:?}", contact.email, e);
-                    (sent, failed + 1)
-                }
+    let sender = email_helper.sender(
+        newsletter.from_name.as_deref(),
+        newsletter.from_email.as_deref(),
+        newsletter.reply_to.as_deref(),
+    );
+
+    let mut results = Vec::with_capacity(emails.len());
+    for email in emails {
+        let id = Uuid::new_v4().to_string();
+        let delivery = match sqlx::query_as::<_, QueuedDelivery>(
+            "select ? as id, ? as email, first_name, last_name, address, postal_code, city,
+                custom_fields, unsubscribe_token, tracking_disabled, 0 as attempts
+             from contacts where lower(email) = ?",
+        )
+        .bind(&id)
+        .bind(&email)
+        .bind(email.trim().to_lowercase())
+        .fetch_optional(&state.db_pool)
+        .await
+        {
+            Ok(Some(delivery)) => delivery,
+            Ok(None) => QueuedDelivery {
+                id,
+                email: email.clone(),
+                first_name: None,
+                last_name: None,
+                address: None,
+                postal_code: None,
+                city: None,
+                custom_fields: None,
+                unsubscribe_token: None,
+                tracking_disabled: false,
+                attempts: 0,
+            },
+            Err(e) => {
+                error!("Erreur de récupération du contact {}: {:?}", email, e);
+                return response_err(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Erreur de base de données".into(),
+                );
+            }
+        };
+
+        let Personalized {
+            subject,
+            body,
+            text,
+        } = sender::personalize(&newsletter, &delivery, &rendered);
+        let subject = format!("[TEST] {}", subject);
+        let sender = sender.clone();
+        // lettre's SMTP transport is blocking.
+        let result = tokio::task::spawn_blocking(move || {
+            email_helper
+                .send_email(
+                    &sender,
+                    &delivery.id,
+                    &delivery.email,
+                    &subject,
+                    &body,
+                    text.as_deref(),
+                )
+                .map(|_| ())
+        })
+        .await;
+        let error = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(failure)) => Some(failure.message),
+            Err(e) => {
+                error!("Tâche d'envoi de test interrompue pour {}: {:?}", email, e);
+                Some(e.to_string())
             }
+        };
+        results.push(TestSendResult {
+            email,
+            sent: error.is_none(),
+            error,
         });
+    }
 
-    response_success(
-        axum::http::StatusCode::OK,
-        format!("Newsletter envoyée: {} réussites, {} échecs", sent, failed),
-    )
+    response_success(StatusCode::OK, results)
+}
+
+/// GET /newsletters/{id}/preflight
+///
+/// The checks `send` runs before queuing deliveries, errors blocking it.
+#[tracing::instrument(skip(state))]
+pub async fn preflight_newsletter(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+) -> Response {
+    let newsletter = match find_newsletter_for_send(&state.db_pool, &newsletter_id).await {
+        Ok(Some(newsletter)) => newsletter,
+        Ok(None) => return response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(e) => {
+            error!(
+                "Erreur de récupération de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            );
+        }
+    };
+    match preflight::check(&state.db_pool, &newsletter).await {
+        Ok(preflight) => response_success(StatusCode::OK, preflight),
+        Err(e) => {
+            error!(
+                "Erreur de vérification de la newsletter {}: {:?}",
+                newsletter.id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            )
+        }
+    }
+}
+
+/// GET /newsletters/{id}/spam_score
+///
+/// Score of the newsletter against the spam rules, with the rules it matched.
+#[tracing::instrument(skip(state))]
+pub async fn newsletter_spam_score(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+) -> Response {
+    match find_newsletter_for_send(&state.db_pool, &newsletter_id).await {
+        Ok(Some(newsletter)) => {
+            let rendered = render::render_body(&newsletter);
+            response_success(
+                StatusCode::OK,
+                SpamRules::get().score(&newsletter, &rendered),
+            )
+        }
+        Ok(None) => response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".into()),
+        Err(e) => {
+            error!(
+                "Erreur de récupération de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".into(),
+            )
+        }
+    }
 }
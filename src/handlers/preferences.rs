@@ -0,0 +1,267 @@
+use crate::AppState;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::helpers::webhooks::{self, EVENT_SUBSCRIBED, EVENT_UNSUBSCRIBED};
+use crate::models::preferences::{
+    PreferenceContact, PreferenceList, PreferencesResponse, UpdatePreferencesRequest,
+};
+use axum::Json;
+use axum::extract::Path;
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::{Row, SqlitePool};
+use tracing::error;
+use uuid::Uuid;
+use validator::Validate;
+
+async fn find_contact(
+    pool: &SqlitePool,
+    token: &str,
+) -> Result<Option<PreferenceContact>, sqlx::Error> {
+    sqlx::query_as::<_, PreferenceContact>(
+        "select id, email, first_name, last_name, city, paused_until, tracking_disabled from contacts where unsubscribe_token = ?",
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await
+}
+
+async fn load_preferences(
+    pool: &SqlitePool,
+    contact: PreferenceContact,
+) -> Result<PreferencesResponse, sqlx::Error> {
+    let lists = sqlx::query_as::<_, PreferenceList>(
+        r#"
+        select cl.id, cl.name, cl.is_public, clm.contact_id is not null as subscribed
+        from contact_lists cl
+        left join contact_list_members clm on clm.list_id = cl.id and clm.contact_id = ?
+        where cl.is_public = 1 or clm.contact_id is not null
+        order by cl.name
+        "#,
+    )
+    .bind(&contact.id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(PreferencesResponse {
+        email: contact.email,
+        first_name: contact.first_name,
+        last_name: contact.last_name,
+        city: contact.city,
+        paused_until: contact.paused_until,
+        tracking_disabled: contact.tracking_disabled,
+        lists,
+    })
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn get_preferences(State(state): State<AppState>, Path(token): Path<String>) -> Response {
+    let contact = match find_contact(&state.db_pool, &token).await {
+        Ok(Some(contact)) => contact,
+        Ok(None) => {
+            return response_err(StatusCode::NOT_FOUND, "Lien invalide".to_string());
+        }
+        Err(e) => {
+            error!("Erreur de récupération du contact: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    match load_preferences(&state.db_pool, contact).await {
+        Ok(preferences) => response_success(StatusCode::OK, preferences),
+        Err(e) => {
+            error!("Erreur de récupération des préférences: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn update_preferences(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Json(payload): Json<UpdatePreferencesRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let contact = match find_contact(&state.db_pool, &token).await {
+        Ok(Some(contact)) => contact,
+        Ok(None) => {
+            return response_err(StatusCode::NOT_FOUND, "Lien invalide".to_string());
+        }
+        Err(e) => {
+            error!("Erreur de récupération du contact: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    for list_id in payload.join_lists.iter().flatten() {
+        let list = sqlx::query("select is_public from contact_lists where id = ?")
+            .bind(list_id)
+            .fetch_optional(&state.db_pool)
+            .await;
+        match list {
+            Ok(Some(row)) if row.get::<bool, _>("is_public") => {}
+            Ok(_) => {
+                return response_err(
+                    StatusCode::BAD_REQUEST,
+                    format!("La liste {} n'est pas disponible", list_id),
+                );
+            }
+            Err(e) => {
+                error!("Erreur de récupération de la liste {}: {:?}", list_id, e);
+                return response_err(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Erreur de base de données".to_string(),
+                );
+            }
+        }
+    }
+
+    let paused_until = match payload.pause_days {
+        Some(0) => Some(None),
+        Some(days) => Some(Some(Utc::now() + Duration::days(days.into()))),
+        None => None,
+    };
+
+    let mut tx = match state.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Erreur d'ouverture de transaction: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    let mut events = Vec::new();
+    let result: Result<(), sqlx::Error> = async {
+        sqlx::query(
+            "update contacts
+             set first_name = coalesce(?, first_name),
+                 last_name = coalesce(?, last_name),
+                 city = coalesce(?, city),
+                 tracking_disabled = coalesce(?, tracking_disabled),
+                 updated_at = ?
+             where id = ?",
+        )
+        .bind(&payload.first_name)
+        .bind(&payload.last_name)
+        .bind(&payload.city)
+        .bind(payload.tracking_disabled)
+        .bind(Utc::now())
+        .bind(&contact.id)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(paused_until) = paused_until {
+            sqlx::query("update contacts set paused_until = ? where id = ?")
+                .bind(paused_until)
+                .bind(&contact.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        for list_id in payload.join_lists.iter().flatten() {
+            let joined = sqlx::query(
+                "insert or ignore into contact_list_members (contact_id, list_id) values (?, ?)",
+            )
+            .bind(&contact.id)
+            .bind(list_id)
+            .execute(&mut *tx)
+            .await?;
+            if joined.rows_affected() > 0 {
+                events.push((EVENT_SUBSCRIBED, list_id));
+            }
+        }
+
+        for list_id in payload.leave_lists.iter().flatten() {
+            let removed =
+                sqlx::query("delete from contact_list_members where contact_id = ? and list_id = ?")
+                    .bind(&contact.id)
+                    .bind(list_id)
+                    .execute(&mut *tx)
+                    .await?;
+            if removed.rows_affected() > 0 {
+                sqlx::query(
+                    "insert into unsubscriptions (id, contact_id, list_id, created_at) values (?, ?, ?, ?)",
+                )
+                .bind(Uuid::new_v4().to_string())
+                .bind(&contact.id)
+                .bind(list_id)
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await?;
+                events.push((EVENT_UNSUBSCRIBED, list_id));
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        error!("Erreur lors de la mise à jour des préférences: {:?}", e);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de la mise à jour des préférences".to_string(),
+        );
+    }
+    if let Err(e) = tx.commit().await {
+        error!("Erreur lors de la validation de la transaction: {:?}", e);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de la mise à jour des préférences".to_string(),
+        );
+    }
+
+    for (event, list_id) in events {
+        webhooks::emit(
+            &state.db_pool,
+            event,
+            json!({ "contact_id": contact.id, "email": contact.email, "list_id": list_id }),
+        )
+        .await;
+    }
+
+    let contact = match find_contact(&state.db_pool, &token).await {
+        Ok(Some(contact)) => contact,
+        Ok(None) => {
+            return response_err(StatusCode::NOT_FOUND, "Lien invalide".to_string());
+        }
+        Err(e) => {
+            error!("Erreur de récupération du contact: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    match load_preferences(&state.db_pool, contact).await {
+        Ok(preferences) => response_success(StatusCode::OK, preferences),
+        Err(e) => {
+            error!("Erreur de récupération des préférences: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::AppState;
+use crate::helpers::email::Email;
+use crate::helpers::response::{response_err, response_success};
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use tracing::error;
+
+/// GET /relays
+///
+/// Configuration and health of the SMTP relays, with their deliveries over
+/// the last 24 hours.
+#[tracing::instrument(skip(state))]
+pub async fn list_relays(State(state): State<AppState>) -> Response {
+    let counts: Vec<(String, i64, i64, i64)> = match sqlx::query_as(
+        r#"
+        select relay,
+            coalesce(sum(status = 'sent'), 0),
+            coalesce(sum(status = 'failed'), 0),
+            coalesce(sum(status = 'bounced'), 0)
+        from deliveries
+        where relay is not null and sent_at >= ?
+        group by relay
+        "#,
+    )
+    .bind(Utc::now() - chrono::Duration::hours(24))
+    .fetch_all(&state.db_pool)
+    .await
+    {
+        Ok(counts) => counts,
+        Err(e) => {
+            error!(
+                "Erreur de récupération des statistiques des relais: {:?}",
+                e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    let mut relays = Email::get().relay_stats();
+    for relay in &mut relays {
+        if let Some((_, sent, failed, bounced)) =
+            counts.iter().find(|(name, ..)| *name == relay.name)
+        {
+            relay.sent_last_24h = *sent;
+            relay.failed_last_24h = *failed;
+            relay.bounced_last_24h = *bounced;
+        }
+    }
+
+    response_success(StatusCode::OK, relays)
+}
@@ -0,0 +1,99 @@
+use crate::AppState;
+use crate::helpers::audit;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::helpers::suppressions::normalize_email;
+use crate::models::seed_addresses::{NewSeedAddressRequest, SeedAddress};
+use axum::Json;
+use axum::extract::Path;
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use tracing::error;
+use validator::Validate;
+
+#[tracing::instrument(skip(state))]
+pub async fn list_seed_addresses(State(state): State<AppState>) -> Response {
+    match sqlx::query_as::<_, SeedAddress>(
+        "select email, created_at from seed_addresses order by email",
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    {
+        Ok(seeds) => response_success(StatusCode::OK, seeds),
+        Err(e) => {
+            error!("Erreur de récupération des adresses témoins: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn create_seed_address(
+    State(state): State<AppState>,
+    Json(payload): Json<NewSeedAddressRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let email = normalize_email(&payload.email);
+    let result =
+        sqlx::query("insert or ignore into seed_addresses (email, created_at) values (?, ?)")
+            .bind(&email)
+            .bind(Utc::now())
+            .execute(&state.db_pool)
+            .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => response_err(
+            StatusCode::CONFLICT,
+            "Adresse déjà présente dans la liste témoin".to_string(),
+        ),
+        Ok(_) => audit::with_target(
+            response_success(StatusCode::CREATED, "Adresse ajoutée à la liste témoin"),
+            email,
+        ),
+        Err(e) => {
+            error!("Erreur lors de l'ajout de l'adresse témoin: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de l'ajout de l'adresse témoin".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn delete_seed_address(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+) -> Response {
+    let result = sqlx::query("delete from seed_addresses where email = ?")
+        .bind(normalize_email(&email))
+        .execute(&state.db_pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => response_err(
+            StatusCode::NOT_FOUND,
+            "Adresse absente de la liste témoin".to_string(),
+        ),
+        Ok(_) => response_success(StatusCode::OK, "Adresse retirée de la liste témoin"),
+        Err(e) => {
+            error!(
+                "Erreur lors de la suppression de l'adresse témoin {}: {:?}",
+                email, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
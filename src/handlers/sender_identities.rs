@@ -0,0 +1,222 @@
+use crate::AppState;
+use crate::helpers::audit;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::models::sender_identities::{
+    NewSenderIdentityRequest, SenderIdentity, UpdateSenderIdentityRequest,
+};
+use axum::Json;
+use axum::extract::Path;
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use sqlx::SqlitePool;
+use tracing::error;
+use uuid::Uuid;
+use validator::Validate;
+
+pub async fn find_sender_identity(
+    pool: &SqlitePool,
+    id: &str,
+) -> Result<Option<SenderIdentity>, sqlx::Error> {
+    sqlx::query_as::<_, SenderIdentity>(
+        "select id, from_name, from_email, reply_to, created_at, updated_at
+         from sender_identities where id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+fn found_identity(
+    id: &str,
+    identity: Result<Option<SenderIdentity>, sqlx::Error>,
+    status: StatusCode,
+) -> Response {
+    match identity {
+        Ok(Some(identity)) => response_success(status, identity),
+        Ok(None) => response_err(
+            StatusCode::NOT_FOUND,
+            "Identité d'expéditeur non trouvée".to_string(),
+        ),
+        Err(e) => {
+            error!(
+                "Erreur de récupération de l'identité d'expéditeur {}: {:?}",
+                id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn list_sender_identities(State(state): State<AppState>) -> Response {
+    match sqlx::query_as::<_, SenderIdentity>(
+        "select id, from_name, from_email, reply_to, created_at, updated_at
+         from sender_identities order by from_name",
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    {
+        Ok(identities) => response_success(StatusCode::OK, identities),
+        Err(e) => {
+            error!("Erreur de récupération des identités d'expéditeur: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn create_sender_identity(
+    State(state): State<AppState>,
+    Json(payload): Json<NewSenderIdentityRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let id = Uuid::new_v4().to_string();
+    if let Err(e) = sqlx::query(
+        "insert into sender_identities (id, from_name, from_email, reply_to, created_at, updated_at)
+         values (?, ?, ?, nullif(?, ''), ?, ?)",
+    )
+    .bind(&id)
+    .bind(payload.from_name.trim())
+    .bind(payload.from_email.trim())
+    .bind(payload.reply_to.as_deref().map(str::trim))
+    .bind(Utc::now())
+    .bind(Utc::now())
+    .execute(&state.db_pool)
+    .await
+    {
+        error!("Erreur lors de la création de l'identité d'expéditeur: {:?}", e);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de la création de l'identité d'expéditeur".to_string(),
+        );
+    }
+
+    let identity = find_sender_identity(&state.db_pool, &id).await;
+    audit::with_target(found_identity(&id, identity, StatusCode::CREATED), id)
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn update_sender_identity(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateSenderIdentityRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let reply_to = payload.reply_to.as_deref().map(str::trim);
+    let result = sqlx::query(
+        "update sender_identities
+         set from_name = coalesce(?, from_name),
+             from_email = coalesce(?, from_email),
+             reply_to = case when ? is null then reply_to else nullif(?, '') end,
+             updated_at = ?
+         where id = ?",
+    )
+    .bind(payload.from_name.as_deref().map(str::trim))
+    .bind(payload.from_email.as_deref().map(str::trim))
+    .bind(reply_to)
+    .bind(reply_to)
+    .bind(Utc::now())
+    .bind(&id)
+    .execute(&state.db_pool)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => {
+            return response_err(
+                StatusCode::NOT_FOUND,
+                "Identité d'expéditeur non trouvée".to_string(),
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!(
+                "Erreur lors de la mise à jour de l'identité d'expéditeur {}: {:?}",
+                id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de la mise à jour de l'identité d'expéditeur".to_string(),
+            );
+        }
+    }
+
+    let identity = find_sender_identity(&state.db_pool, &id).await;
+    found_identity(&id, identity, StatusCode::OK)
+}
+
+/// Refused while a newsletter waiting to go out uses the identity, it would
+/// silently fall back to the configured one.
+#[tracing::instrument(skip(state))]
+pub async fn delete_sender_identity(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let in_use = sqlx::query(
+        "select 1 from sendings
+         where sender_identity_id = ? and status in ('scheduled', 'sending', 'paused')",
+    )
+    .bind(&id)
+    .fetch_optional(&state.db_pool)
+    .await;
+    match in_use {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return response_err(
+                StatusCode::CONFLICT,
+                "Identité utilisée par une newsletter en attente d'envoi".to_string(),
+            );
+        }
+        Err(e) => {
+            error!(
+                "Erreur de vérification de l'identité d'expéditeur {}: {:?}",
+                id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    }
+
+    let result = sqlx::query("delete from sender_identities where id = ?")
+        .bind(&id)
+        .execute(&state.db_pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => response_err(
+            StatusCode::NOT_FOUND,
+            "Identité d'expéditeur non trouvée".to_string(),
+        ),
+        Ok(_) => response_success(StatusCode::OK, "Identité d'expéditeur supprimée"),
+        Err(e) => {
+            error!(
+                "Erreur lors de la suppression de l'identité d'expéditeur {}: {:?}",
+                id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
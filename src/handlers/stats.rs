@@ -0,0 +1,213 @@
+use crate::AppState;
+use crate::helpers::response::{response_err, response_success};
+use crate::models::stats::{
+    BounceCounts, BounceStats, ClickStats, DeliveryCounts, EventCounts, LinkClicks,
+    NewsletterStats, SeriesBucket, SeriesRow,
+};
+use axum::extract::Path;
+use axum::{extract::State, http::StatusCode, response::Response};
+use chrono::Duration;
+use sqlx::{Row, SqlitePool};
+use tracing::error;
+
+/// Number of hourly buckets in the engagement series.
+const SERIES_HOURS: i64 = 48;
+
+async fn newsletter_stats(
+    pool: &SqlitePool,
+    newsletter_id: &str,
+) -> Result<Option<NewsletterStats>, sqlx::Error> {
+    let exists = sqlx::query("select 1 from sendings where id = ? and type = 'newsletter'")
+        .bind(newsletter_id)
+        .fetch_optional(pool)
+        .await?;
+    if exists.is_none() {
+        return Ok(None);
+    }
+
+    // Copies sent to the seed addresses are left out of every count.
+    let deliveries = sqlx::query_as::<_, DeliveryCounts>(
+        r#"
+        select coalesce(sum(status = 'sent' or (status = 'bounced' and message_id is not null)), 0) as sent,
+            coalesce(sum(status = 'failed' or (status = 'bounced' and message_id is null)), 0) as failed,
+            coalesce(sum(status = 'suppressed'), 0) as suppressed,
+            min(sent_at) as first_sent_at
+        from deliveries
+        where sending_id = ? and not is_seed
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_one(pool)
+    .await?;
+
+    let bounces = sqlx::query_as::<_, BounceCounts>(
+        r#"
+        select count(distinct b.delivery_id) as bounced,
+            count(distinct case when b.kind = 'hard' then b.delivery_id end) as hard,
+            count(distinct case when b.kind = 'soft' then b.delivery_id end) as soft
+        from bounces b
+        join deliveries d on d.id = b.delivery_id
+        where d.sending_id = ? and not d.is_seed
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_one(pool)
+    .await?;
+
+    let opens = sqlx::query_as::<_, EventCounts>(
+        r#"
+        select count(distinct o.delivery_id) as "unique", count(*) as total
+        from open_events o
+        join deliveries d on d.id = o.delivery_id
+        where d.sending_id = ? and not d.is_seed
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_one(pool)
+    .await?;
+
+    let clicks = sqlx::query_as::<_, EventCounts>(
+        r#"
+        select count(distinct c.delivery_id) as "unique", count(*) as total
+        from click_events c
+        join deliveries d on d.id = c.delivery_id
+        where d.sending_id = ? and not d.is_seed
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_one(pool)
+    .await?;
+
+    let links = sqlx::query_as::<_, LinkClicks>(
+        r#"
+        select c.url, count(distinct c.delivery_id) as "unique", count(*) as total
+        from click_events c
+        join deliveries d on d.id = c.delivery_id
+        where d.sending_id = ? and not d.is_seed
+        group by c.url
+        order by total desc, c.url
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_all(pool)
+    .await?;
+
+    // Only unsubscriptions from one of the targeted lists, after the
+    // newsletter reached the contact, are attributed to it.
+    let unsubscribes: i64 = sqlx::query(
+        r#"
+        select count(distinct u.contact_id) as count
+        from unsubscriptions u
+        join deliveries d on d.contact_id = u.contact_id
+        join sending_contact_lists scl
+            on scl.sending_id = d.sending_id and scl.contact_list_id = u.list_id
+        where d.sending_id = ? and d.sent_at is not null and u.created_at >= d.sent_at
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_one(pool)
+    .await?
+    .get("count");
+
+    let complaints: i64 = sqlx::query(
+        r#"
+        select count(distinct c.delivery_id) as count
+        from complaints c
+        join deliveries d on d.id = c.delivery_id
+        where d.sending_id = ? and not d.is_seed
+        "#,
+    )
+    .bind(newsletter_id)
+    .fetch_one(pool)
+    .await?
+    .get("count");
+
+    let mut series = Vec::new();
+    if let Some(start) = deliveries.first_sent_at {
+        series = (0..SERIES_HOURS)
+            .map(|hour| SeriesBucket {
+                start: start + Duration::hours(hour),
+                opens: 0,
+                clicks: 0,
+            })
+            .collect();
+
+        let rows = sqlx::query_as::<_, SeriesRow>(
+            r#"
+            select 'open' as kind,
+                cast((julianday(o.created_at) - julianday(?1)) * 24 as integer) as bucket,
+                count(*) as count
+            from open_events o
+            join deliveries d on d.id = o.delivery_id
+            where d.sending_id = ?2 and not d.is_seed and o.created_at >= ?1
+            group by bucket
+            union all
+            select 'click' as kind,
+                cast((julianday(c.created_at) - julianday(?1)) * 24 as integer) as bucket,
+                count(*) as count
+            from click_events c
+            join deliveries d on d.id = c.delivery_id
+            where d.sending_id = ?2 and not d.is_seed and c.created_at >= ?1
+            group by bucket
+            "#,
+        )
+        .bind(start)
+        .bind(newsletter_id)
+        .fetch_all(pool)
+        .await?;
+
+        for row in rows {
+            let Some(bucket) = usize::try_from(row.bucket)
+                .ok()
+                .and_then(|index| series.get_mut(index))
+            else {
+                continue;
+            };
+            match row.kind.as_str() {
+                "open" => bucket.opens = row.count,
+                _ => bucket.clicks = row.count,
+            }
+        }
+    }
+
+    Ok(Some(NewsletterStats {
+        sent: deliveries.sent,
+        failed: deliveries.failed,
+        suppressed: deliveries.suppressed,
+        bounced: BounceStats {
+            total: bounces.bounced,
+            hard: bounces.hard,
+            soft: bounces.soft,
+        },
+        opens,
+        clicks: ClickStats {
+            unique: clicks.unique,
+            total: clicks.total,
+            links,
+        },
+        unsubscribes,
+        complaints,
+        series,
+    }))
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn get_newsletter_stats(
+    State(state): State<AppState>,
+    Path(newsletter_id): Path<String>,
+) -> Response {
+    match newsletter_stats(&state.db_pool, &newsletter_id).await {
+        Ok(Some(stats)) => response_success(StatusCode::OK, stats),
+        Ok(None) => response_err(StatusCode::NOT_FOUND, "Newsletter non trouvée".to_string()),
+        Err(e) => {
+            error!(
+                "Erreur de calcul des statistiques de la newsletter {}: {:?}",
+                newsletter_id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
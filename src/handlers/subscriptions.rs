@@ -0,0 +1,169 @@
+use crate::AppState;
+use crate::helpers::anti_abuse::AntiAbuse;
+use crate::helpers::audit;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::helpers::signing::random_token;
+use crate::helpers::webhooks::{self, EVENT_SUBSCRIBED};
+use crate::models::subscriptions::{ChallengeResponse, SubscribeRequest};
+use axum::Json;
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use serde_json::json;
+use sqlx::Row;
+use tracing::{error, info};
+use uuid::Uuid;
+use validator::Validate;
+
+#[tracing::instrument]
+pub async fn get_challenge() -> Response {
+    let anti_abuse = AntiAbuse::get();
+
+    response_success(
+        StatusCode::OK,
+        ChallengeResponse {
+            required: anti_abuse.pow_enabled(),
+            challenge: anti_abuse.issue_challenge(),
+            difficulty: anti_abuse.pow_difficulty(),
+        },
+    )
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn subscribe(
+    State(state): State<AppState>,
+    Json(payload): Json<SubscribeRequest>,
+) -> Response {
+    // Bots get the same answer as humans so they don't learn about the trap.
+    if payload.website.as_deref().is_some_and(|v| !v.is_empty()) {
+        info!(
+            "Inscription ignorée (honeypot rempli) pour {}",
+            payload.email
+        );
+        return response_success(StatusCode::CREATED, "Inscription enregistrée");
+    }
+
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let anti_abuse = AntiAbuse::get();
+    if anti_abuse.pow_enabled() {
+        let (Some(challenge), Some(nonce)) = (&payload.pow_challenge, &payload.pow_nonce) else {
+            return response_err(
+                StatusCode::BAD_REQUEST,
+                "Preuve de travail manquante".to_string(),
+            );
+        };
+        if let Err(msg) = anti_abuse.verify_pow(challenge, nonce) {
+            return response_err(StatusCode::BAD_REQUEST, msg.to_string());
+        }
+    }
+
+    let email = payload.email.trim().to_lowercase();
+    if anti_abuse.is_disposable(&email) {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            "Les adresses e-mail jetables ne sont pas acceptées".to_string(),
+        );
+    }
+
+    let list = match sqlx::query("select id from contact_lists where id = ? and is_public = 1")
+        .bind(&payload.list_id)
+        .fetch_optional(&state.db_pool)
+        .await
+    {
+        Ok(list) => list,
+        Err(e) => {
+            error!(
+                "Erreur de récupération de la liste {}: {:?}",
+                payload.list_id, e
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+    if list.is_none() {
+        return response_err(
+            StatusCode::NOT_FOUND,
+            "Liste de contacts non trouvée".to_string(),
+        );
+    }
+
+    if let Err(err) = sqlx::query(
+        "insert into contacts (id, first_name, last_name, email, unsubscribe_token, created_at, updated_at)
+         values (?, ?, ?, ?, ?, ?, ?)
+         on conflict (email) do nothing",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&payload.first_name)
+    .bind(&payload.last_name)
+    .bind(&email)
+    .bind(random_token(32))
+    .bind(Utc::now())
+    .bind(Utc::now())
+    .execute(&state.db_pool)
+    .await
+    {
+        error!("Erreur lors de la création du contact: {:?}", err);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de l'inscription".to_string(),
+        );
+    }
+
+    let contact_id: String = match sqlx::query("select id from contacts where email = ?")
+        .bind(&email)
+        .fetch_one(&state.db_pool)
+        .await
+    {
+        Ok(row) => row.get("id"),
+        Err(err) => {
+            error!("Erreur de récupération du contact {}: {:?}", email, err);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de l'inscription".to_string(),
+            );
+        }
+    };
+
+    let membership = match sqlx::query(
+        "insert or ignore into contact_list_members (contact_id, list_id) values (?, ?)",
+    )
+    .bind(&contact_id)
+    .bind(&payload.list_id)
+    .execute(&state.db_pool)
+    .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            error!(
+                "Erreur lors de l'association du contact à la liste: {:?}",
+                err
+            );
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de l'inscription".to_string(),
+            );
+        }
+    };
+
+    if membership.rows_affected() > 0 {
+        webhooks::emit(
+            &state.db_pool,
+            EVENT_SUBSCRIBED,
+            json!({ "contact_id": contact_id, "email": email, "list_id": payload.list_id }),
+        )
+        .await;
+    }
+
+    audit::with_target(
+        response_success(StatusCode::CREATED, "Inscription enregistrée"),
+        contact_id,
+    )
+}
@@ -0,0 +1,162 @@
+use crate::AppState;
+use crate::helpers::audit;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::helpers::suppressions::{
+    SOURCE_IMPORT, SOURCE_MANUAL, normalize_email, parse_suppression_file, suppress,
+};
+use crate::models::suppressions::{
+    ImportSuppressionsQuery, ImportSuppressionsResponse, NewSuppressionRequest, Suppression,
+    SuppressionsQuery,
+};
+use axum::Json;
+use axum::extract::{Path, Query};
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use tracing::error;
+use validator::Validate;
+
+#[tracing::instrument(skip(state))]
+pub async fn list_suppressions(
+    State(state): State<AppState>,
+    Query(query): Query<SuppressionsQuery>,
+) -> Response {
+    let search = format!("%{}%", query.search.unwrap_or_default().to_lowercase());
+    let suppressions = match sqlx::query_as::<_, Suppression>(
+        "select email, reason, source, created_at from suppressions where email like ? order by created_at desc",
+    )
+    .bind(search)
+    .fetch_all(&state.db_pool)
+    .await
+    {
+        Ok(list) => list,
+        Err(e) => {
+            error!("Erreur de récupération des suppressions: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    response_success(StatusCode::OK, suppressions)
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn create_suppression(
+    State(state): State<AppState>,
+    Json(payload): Json<NewSuppressionRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let reason = payload.reason.as_deref().unwrap_or("manual");
+    match suppress(&state.db_pool, &payload.email, reason, SOURCE_MANUAL).await {
+        Ok(true) => audit::with_target(
+            response_success(
+                StatusCode::CREATED,
+                "Adresse ajoutée à la liste de suppression",
+            ),
+            normalize_email(&payload.email),
+        ),
+        Ok(false) => response_err(
+            StatusCode::CONFLICT,
+            "Adresse déjà présente dans la liste de suppression".to_string(),
+        ),
+        Err(e) => {
+            error!("Erreur lors de l'ajout de la suppression: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de l'ajout de la suppression".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn delete_suppression(
+    State(state): State<AppState>,
+    Path(email): Path<String>,
+) -> Response {
+    let result = sqlx::query("delete from suppressions where email = ?")
+        .bind(normalize_email(&email))
+        .execute(&state.db_pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => response_err(
+            StatusCode::NOT_FOUND,
+            "Adresse absente de la liste de suppression".to_string(),
+        ),
+        Ok(_) => response_success(StatusCode::OK, "Adresse retirée de la liste de suppression"),
+        Err(e) => {
+            error!("Erreur lors de la suppression de {}: {:?}", email, e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+/// Bulk import of a suppression export (CSV, TSV or one address per line)
+/// sent as the raw request body.
+#[tracing::instrument(skip(state, body))]
+pub async fn import_suppressions(
+    State(state): State<AppState>,
+    Query(query): Query<ImportSuppressionsQuery>,
+    body: String,
+) -> Response {
+    let entries = parse_suppression_file(&body);
+    if entries.is_empty() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            "Aucune adresse trouvée dans le fichier".to_string(),
+        );
+    }
+
+    let default_reason = query.reason.unwrap_or_else(|| "import".to_string());
+    let mut tx = match state.db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Erreur d'ouverture de transaction: {:?}", e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    };
+
+    let mut imported = 0;
+    let mut skipped = 0;
+    for (email, reason) in entries {
+        let reason = reason.as_deref().unwrap_or(&default_reason);
+        match suppress(&mut *tx, &email, reason, SOURCE_IMPORT).await {
+            Ok(true) => imported += 1,
+            Ok(false) => skipped += 1,
+            Err(e) => {
+                error!("Erreur lors de l'import de {}: {:?}", email, e);
+                return response_err(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Erreur lors de l'import des suppressions".to_string(),
+                );
+            }
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        error!("Erreur lors de la validation de l'import: {:?}", e);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de l'import des suppressions".to_string(),
+        );
+    }
+
+    response_success(
+        StatusCode::OK,
+        ImportSuppressionsResponse { imported, skipped },
+    )
+}
@@ -0,0 +1,277 @@
+use crate::AppState;
+use crate::helpers::audit;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::helpers::sanitize::{sanitize_html, sanitize_stylesheet};
+use crate::models::themes::{NewThemeRequest, SavedTheme, Theme, UpdateThemeRequest};
+use axum::Json;
+use axum::extract::Path;
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use lol_html::errors::RewritingError;
+use sqlx::SqlitePool;
+use tracing::error;
+use uuid::Uuid;
+use validator::Validate;
+
+pub async fn find_theme(pool: &SqlitePool, id: &str) -> Result<Option<Theme>, sqlx::Error> {
+    sqlx::query_as::<_, Theme>(
+        "select id, name, header, footer, stylesheet, created_at, updated_at
+         from themes where id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Header, footer and stylesheet of a theme as stored.
+struct SanitizedTheme {
+    header: Option<String>,
+    footer: Option<String>,
+    stylesheet: Option<String>,
+    /// What was taken out of them.
+    removed: Vec<String>,
+}
+
+fn sanitize_theme(
+    header: Option<&str>,
+    footer: Option<&str>,
+    stylesheet: Option<&str>,
+) -> Result<SanitizedTheme, RewritingError> {
+    let mut removed = Vec::new();
+    let mut sanitize_part = |part: Option<&str>| {
+        part.map(|part| {
+            let sanitized = sanitize_html(part)?;
+            removed.extend(sanitized.removed);
+            Ok(sanitized.html)
+        })
+        .transpose()
+    };
+    let header = sanitize_part(header)?;
+    let footer = sanitize_part(footer)?;
+    let stylesheet = stylesheet.map(|css| {
+        let (css, dropped) = sanitize_stylesheet(css);
+        match dropped {
+            0 => {}
+            1 => removed.push("déclaration CSS de la feuille de style".to_string()),
+            count => removed.push(format!(
+                "déclaration CSS de la feuille de style (x{})",
+                count
+            )),
+        }
+        css
+    });
+    Ok(SanitizedTheme {
+        header,
+        footer,
+        stylesheet,
+        removed,
+    })
+}
+
+/// Rejects a theme whose HTML could not be read, rather than storing it
+/// unchecked or empty.
+fn unreadable_theme(e: RewritingError) -> Response {
+    error!("Erreur lors du nettoyage du HTML du thème: {:?}", e);
+    response_err(
+        StatusCode::BAD_REQUEST,
+        "HTML du thème illisible".to_string(),
+    )
+}
+
+fn found_theme(
+    id: &str,
+    theme: Result<Option<Theme>, sqlx::Error>,
+    status: StatusCode,
+    removed: Vec<String>,
+) -> Response {
+    match theme {
+        Ok(Some(theme)) => response_success(status, SavedTheme { theme, removed }),
+        Ok(None) => response_err(StatusCode::NOT_FOUND, "Thème non trouvé".to_string()),
+        Err(e) => {
+            error!("Erreur de récupération du thème {}: {:?}", id, e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn list_themes(State(state): State<AppState>) -> Response {
+    match sqlx::query_as::<_, Theme>(
+        "select id, name, header, footer, stylesheet, created_at, updated_at
+         from themes order by name",
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    {
+        Ok(themes) => response_success(StatusCode::OK, themes),
+        Err(e) => {
+            error!("Erreur de récupération des thèmes: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn create_theme(
+    State(state): State<AppState>,
+    Json(payload): Json<NewThemeRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let sanitized = match sanitize_theme(
+        payload.header.as_deref(),
+        payload.footer.as_deref(),
+        payload.stylesheet.as_deref(),
+    ) {
+        Ok(sanitized) => sanitized,
+        Err(e) => return unreadable_theme(e),
+    };
+
+    let id = Uuid::new_v4().to_string();
+    if let Err(e) = sqlx::query(
+        "insert into themes (id, name, header, footer, stylesheet, created_at, updated_at)
+         values (?, ?, nullif(?, ''), nullif(?, ''), nullif(?, ''), ?, ?)",
+    )
+    .bind(&id)
+    .bind(payload.name.trim())
+    .bind(&sanitized.header)
+    .bind(&sanitized.footer)
+    .bind(&sanitized.stylesheet)
+    .bind(Utc::now())
+    .bind(Utc::now())
+    .execute(&state.db_pool)
+    .await
+    {
+        error!("Erreur lors de la création du thème: {:?}", e);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de la création du thème".to_string(),
+        );
+    }
+
+    let theme = find_theme(&state.db_pool, &id).await;
+    audit::with_target(
+        found_theme(&id, theme, StatusCode::CREATED, sanitized.removed),
+        id,
+    )
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn update_theme(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateThemeRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let sanitized = match sanitize_theme(
+        payload.header.as_deref(),
+        payload.footer.as_deref(),
+        payload.stylesheet.as_deref(),
+    ) {
+        Ok(sanitized) => sanitized,
+        Err(e) => return unreadable_theme(e),
+    };
+
+    let result = sqlx::query(
+        "update themes
+         set name = coalesce(?, name),
+             header = case when ? is null then header else nullif(?, '') end,
+             footer = case when ? is null then footer else nullif(?, '') end,
+             stylesheet = case when ? is null then stylesheet else nullif(?, '') end,
+             updated_at = ?
+         where id = ?",
+    )
+    .bind(payload.name.as_deref().map(str::trim))
+    .bind(&sanitized.header)
+    .bind(&sanitized.header)
+    .bind(&sanitized.footer)
+    .bind(&sanitized.footer)
+    .bind(&sanitized.stylesheet)
+    .bind(&sanitized.stylesheet)
+    .bind(Utc::now())
+    .bind(&id)
+    .execute(&state.db_pool)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => {
+            return response_err(StatusCode::NOT_FOUND, "Thème non trouvé".to_string());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Erreur lors de la mise à jour du thème {}: {:?}", id, e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de la mise à jour du thème".to_string(),
+            );
+        }
+    }
+
+    let theme = find_theme(&state.db_pool, &id).await;
+    found_theme(&id, theme, StatusCode::OK, sanitized.removed)
+}
+
+/// Refused while a newsletter waiting to go out uses the theme.
+#[tracing::instrument(skip(state))]
+pub async fn delete_theme(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let in_use = sqlx::query(
+        "select 1 from sendings
+         where theme_id = ? and status in ('scheduled', 'sending', 'paused')",
+    )
+    .bind(&id)
+    .fetch_optional(&state.db_pool)
+    .await;
+    match in_use {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return response_err(
+                StatusCode::CONFLICT,
+                "Thème utilisé par une newsletter en attente d'envoi".to_string(),
+            );
+        }
+        Err(e) => {
+            error!("Erreur de vérification du thème {}: {:?}", id, e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    }
+
+    let result = sqlx::query("delete from themes where id = ?")
+        .bind(&id)
+        .execute(&state.db_pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => {
+            response_err(StatusCode::NOT_FOUND, "Thème non trouvé".to_string())
+        }
+        Ok(_) => response_success(StatusCode::OK, "Thème supprimé"),
+        Err(e) => {
+            error!("Erreur lors de la suppression du thème {}: {:?}", id, e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::AppState;
+use crate::helpers::response::response_err;
+use crate::helpers::tracking::{verify_click_token, verify_open_token};
+use crate::helpers::webhooks::{self, EVENT_CLICKED, delivery_event_data};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use chrono::Utc;
+use serde_json::json;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// Transparent 1x1 GIF.
+const PIXEL_GIF: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// GET /t/o/{token}
+#[tracing::instrument(skip(state, headers))]
+pub async fn track_open(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    match verify_open_token(&token) {
+        Some(delivery_id) => {
+            // Contacts who opted out of tracking are never recorded, even if
+            // an older message still carries a pixel.
+            if let Err(e) = sqlx::query(
+                "insert into open_events (id, delivery_id, contact_id, user_agent, created_at)
+                 select ?, d.id, d.contact_id, ?, ?
+                 from deliveries d
+                 left join contacts c on c.id = d.contact_id
+                 where d.id = ? and coalesce(c.tracking_disabled, 0) = 0",
+            )
+            .bind(Uuid::new_v4().to_string())
+            .bind(user_agent(&headers))
+            .bind(Utc::now())
+            .bind(&delivery_id)
+            .execute(&state.db_pool)
+            .await
+            {
+                error!(
+                    "Erreur d'enregistrement de l'ouverture {}: {:?}",
+                    delivery_id, e
+                );
+            }
+        }
+        None => warn!("Jeton d'ouverture invalide"),
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "image/gif"),
+            (
+                header::CACHE_CONTROL,
+                "no-store, no-cache, must-revalidate, private",
+            ),
+        ],
+        PIXEL_GIF,
+    )
+        .into_response()
+}
+
+/// GET /t/c/{token}
+#[tracing::instrument(skip(state, headers))]
+pub async fn track_click(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    // Only signed destinations are followed, this must not become an open redirect.
+    let Some((delivery_id, url)) = verify_click_token(&token) else {
+        return response_err(StatusCode::BAD_REQUEST, "Lien invalide".to_string());
+    };
+
+    let result = sqlx::query(
+        "insert into click_events (id, delivery_id, contact_id, url, user_agent, created_at)
+         select ?, d.id, d.contact_id, ?, ?, ?
+         from deliveries d
+         left join contacts c on c.id = d.contact_id
+         where d.id = ? and coalesce(c.tracking_disabled, 0) = 0",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&url)
+    .bind(user_agent(&headers))
+    .bind(Utc::now())
+    .bind(&delivery_id)
+    .execute(&state.db_pool)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() > 0 => {
+            match delivery_event_data(&state.db_pool, &delivery_id).await {
+                Ok(mut data) => {
+                    data["url"] = json!(url);
+                    webhooks::emit(&state.db_pool, EVENT_CLICKED, data).await;
+                }
+                Err(e) => error!("Erreur de préparation du webhook de clic: {:?}", e),
+            }
+        }
+        Ok(_) => {}
+        Err(e) => error!("Erreur d'enregistrement du clic {}: {:?}", delivery_id, e),
+    }
+
+    Redirect::to(&url).into_response()
+}
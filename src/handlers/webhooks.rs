@@ -0,0 +1,233 @@
+use crate::AppState;
+use crate::helpers::audit;
+use crate::helpers::response::{extract_errors, response_err, response_success};
+use crate::helpers::signing::random_token;
+use crate::models::webhooks::{
+    CreatedWebhook, NewWebhookRequest, UpdateWebhookRequest, Webhook, WebhookDeliveriesQuery,
+    WebhookDelivery, WebhookRow,
+};
+use axum::Json;
+use axum::extract::{Path, Query};
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode};
+use chrono::Utc;
+use sqlx::SqlitePool;
+use tracing::error;
+use uuid::Uuid;
+use validator::Validate;
+
+async fn find_webhook(pool: &SqlitePool, id: &str) -> Result<Option<WebhookRow>, sqlx::Error> {
+    sqlx::query_as::<_, WebhookRow>(
+        "select id, url, events, is_active, created_at, updated_at from webhooks where id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn list_webhooks(State(state): State<AppState>) -> Response {
+    match sqlx::query_as::<_, WebhookRow>(
+        "select id, url, events, is_active, created_at, updated_at from webhooks order by created_at desc",
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    {
+        Ok(rows) => response_success(
+            StatusCode::OK,
+            rows.into_iter().map(Webhook::from).collect::<Vec<_>>(),
+        ),
+        Err(e) => {
+            error!("Erreur de récupération des webhooks: {:?}", e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state, payload))]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    Json(payload): Json<NewWebhookRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let secret = payload.secret.unwrap_or_else(|| random_token(32));
+    if let Err(e) = sqlx::query(
+        "insert into webhooks (id, url, secret, events, is_active, created_at, updated_at)
+         values (?, ?, ?, ?, 1, ?, ?)",
+    )
+    .bind(&id)
+    .bind(&payload.url)
+    .bind(&secret)
+    .bind(payload.events.join(","))
+    .bind(Utc::now())
+    .bind(Utc::now())
+    .execute(&state.db_pool)
+    .await
+    {
+        error!("Erreur lors de la création du webhook: {:?}", e);
+        return response_err(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Erreur lors de la création du webhook".to_string(),
+        );
+    }
+
+    match find_webhook(&state.db_pool, &id).await {
+        Ok(Some(row)) => audit::with_target(
+            response_success(
+                StatusCode::CREATED,
+                CreatedWebhook {
+                    webhook: row.into(),
+                    secret,
+                },
+            ),
+            id,
+        ),
+        Ok(None) => response_err(StatusCode::NOT_FOUND, "Webhook non trouvé".to_string()),
+        Err(e) => {
+            error!("Erreur de récupération du webhook {}: {:?}", id, e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn update_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateWebhookRequest>,
+) -> Response {
+    if let Err(validation_errors) = payload.validate() {
+        return response_err(
+            StatusCode::BAD_REQUEST,
+            format!("Validation error: {}", extract_errors(validation_errors)),
+        );
+    }
+
+    let result = sqlx::query(
+        "update webhooks
+         set url = coalesce(?, url),
+             events = coalesce(?, events),
+             is_active = coalesce(?, is_active),
+             updated_at = ?
+         where id = ?",
+    )
+    .bind(&payload.url)
+    .bind(payload.events.as_ref().map(|events| events.join(",")))
+    .bind(payload.is_active)
+    .bind(Utc::now())
+    .bind(&id)
+    .execute(&state.db_pool)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => {
+            return response_err(StatusCode::NOT_FOUND, "Webhook non trouvé".to_string());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Erreur lors de la mise à jour du webhook {}: {:?}", id, e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur lors de la mise à jour du webhook".to_string(),
+            );
+        }
+    }
+
+    match find_webhook(&state.db_pool, &id).await {
+        Ok(Some(row)) => response_success(StatusCode::OK, Webhook::from(row)),
+        Ok(None) => response_err(StatusCode::NOT_FOUND, "Webhook non trouvé".to_string()),
+        Err(e) => {
+            error!("Erreur de récupération du webhook {}: {:?}", id, e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn delete_webhook(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let result = sqlx::query("delete from webhooks where id = ?")
+        .bind(&id)
+        .execute(&state.db_pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => {
+            response_err(StatusCode::NOT_FOUND, "Webhook non trouvé".to_string())
+        }
+        Ok(_) => response_success(StatusCode::OK, "Webhook supprimé"),
+        Err(e) => {
+            error!("Erreur lors de la suppression du webhook {}: {:?}", id, e);
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
+
+/// Delivery attempts of a webhook, most recent first, to debug a receiver.
+#[tracing::instrument(skip(state))]
+pub async fn list_webhook_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<WebhookDeliveriesQuery>,
+) -> Response {
+    match find_webhook(&state.db_pool, &id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return response_err(StatusCode::NOT_FOUND, "Webhook non trouvé".to_string());
+        }
+        Err(e) => {
+            error!("Erreur de récupération du webhook {}: {:?}", id, e);
+            return response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            );
+        }
+    }
+
+    match sqlx::query_as::<_, WebhookDelivery>(
+        r#"
+        select id, event, payload, status, attempts, next_attempt_at, response_status,
+            response_body, error, created_at, updated_at
+        from webhook_deliveries
+        where webhook_id = ? and (? is null or status = ?)
+        order by created_at desc
+        limit 100
+        "#,
+    )
+    .bind(&id)
+    .bind(&query.status)
+    .bind(&query.status)
+    .fetch_all(&state.db_pool)
+    .await
+    {
+        Ok(deliveries) => response_success(StatusCode::OK, deliveries),
+        Err(e) => {
+            error!(
+                "Erreur de récupération des envois du webhook {}: {:?}",
+                id, e
+            );
+            response_err(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Erreur de base de données".to_string(),
+            )
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{ConnectInfo, Request};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use tracing::{error, warn};
+
+use crate::config::config::AntiAbuseConfig;
+use crate::helpers::response::response_err;
+use crate::helpers::signing;
+
+static ANTI_ABUSE: OnceLock<AntiAbuse> = OnceLock::new();
+const CHALLENGE_TTL: Duration = Duration::from_secs(600);
+const MAX_TRACKED_ENTRIES: usize = 10_000;
+
+#[derive(Debug)]
+pub struct AntiAbuse {
+    rate_limit_requests: u32,
+    rate_limit_window: Duration,
+    login_rate_limit_requests: u32,
+    login_rate_limit_window: Duration,
+    pow_enabled: bool,
+    pow_difficulty: u32,
+    disposable_domains: HashSet<String>,
+    hits: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+    login_hits: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+    used_challenges: Mutex<HashMap<String, Instant>>,
+}
+
+impl AntiAbuse {
+    pub fn init(config: &AntiAbuseConfig) {
+        let helper = Self::new(config);
+        ANTI_ABUSE
+            .set(helper)
+            .expect("AntiAbuse already initialized");
+    }
+
+    pub fn get() -> &'static AntiAbuse {
+        ANTI_ABUSE.get().expect("AntiAbuse not initialized")
+    }
+
+    pub fn new(config: &AntiAbuseConfig) -> Self {
+        let disposable_domains = match &config.disposable_domains_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(content) => content
+                    .lines()
+                    .map(|line| line.trim().to_lowercase())
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .collect(),
+                Err(e) => {
+                    error!(
+                        "Impossible de lire la liste des domaines jetables {}, aucun domaine bloqué: {:?}",
+                        path.display(),
+                        e
+                    );
+                    HashSet::new()
+                }
+            },
+            None => HashSet::new(),
+        };
+
+        Self {
+            rate_limit_requests: config.rate_limit_requests,
+            rate_limit_window: Duration::from_secs(config.rate_limit_window_secs),
+            login_rate_limit_requests: config.login_rate_limit_requests,
+            login_rate_limit_window: Duration::from_secs(config.login_rate_limit_window_secs),
+            pow_enabled: config.pow_enabled,
+            pow_difficulty: config.pow_difficulty,
+            disposable_domains,
+            hits: Mutex::new(HashMap::new()),
+            login_hits: Mutex::new(HashMap::new()),
+            used_challenges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn pow_enabled(&self) -> bool {
+        self.pow_enabled
+    }
+
+    pub fn pow_difficulty(&self) -> u32 {
+        self.pow_difficulty
+    }
+
+    /// Counts a hit for `ip` in the current window and tells whether it is still allowed.
+    pub fn check_rate_limit(&self, ip: IpAddr) -> bool {
+        count_hit(
+            &self.hits,
+            ip,
+            self.rate_limit_requests,
+            self.rate_limit_window,
+        )
+    }
+
+    /// Same as `check_rate_limit`, with its own counters and limits for `/login`.
+    pub fn check_login_rate_limit(&self, ip: IpAddr) -> bool {
+        count_hit(
+            &self.login_hits,
+            ip,
+            self.login_rate_limit_requests,
+            self.login_rate_limit_window,
+        )
+    }
+
+    /// Issues a signed, stateless challenge of the form `timestamp.random.signature`.
+    pub fn issue_challenge(&self) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let random: [u8; 16] = rand::rng().random();
+        let payload = format!("{}.{}", timestamp, hex::encode(random));
+        let signature = signing::sign(&payload);
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Checks that `challenge` was issued by us, is fresh and unused, and that
+    /// `sha256(challenge ":" nonce)` starts with `pow_difficulty` zero bits.
+    pub fn verify_pow(&self, challenge: &str, nonce: &str) -> Result<(), &'static str> {
+        let (payload, signature) = challenge.rsplit_once('.').ok_or("Challenge invalide")?;
+        if !signing::verify(payload, signature) {
+            return Err("Challenge invalide");
+        }
+
+        let timestamp: u64 = payload
+            .split('.')
+            .next()
+            .and_then(|ts| ts.parse().ok())
+            .ok_or("Challenge invalide")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if now.saturating_sub(timestamp) > CHALLENGE_TTL.as_secs() {
+            return Err("Challenge expiré");
+        }
+
+        let digest = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+        if leading_zero_bits(&digest) < self.pow_difficulty {
+            return Err("Preuve de travail invalide");
+        }
+
+        let mut used = self
+            .used_challenges
+            .lock()
+            .expect("challenge lock poisoned");
+        let instant = Instant::now();
+        used.retain(|_, issued| instant.duration_since(*issued) < CHALLENGE_TTL);
+        if used.insert(challenge.to_string(), instant).is_some() {
+            return Err("Challenge déjà utilisé");
+        }
+
+        Ok(())
+    }
+
+    /// True when the domain of `email`, or one of its parent domains, is blocklisted.
+    pub fn is_disposable(&self, email: &str) -> bool {
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return false;
+        };
+        let domain = domain.trim().to_lowercase();
+        let mut candidate = domain.as_str();
+        loop {
+            if self.disposable_domains.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => candidate = parent,
+                _ => return false,
+            }
+        }
+    }
+}
+
+fn count_hit(
+    hits: &Mutex<HashMap<IpAddr, (Instant, u32)>>,
+    ip: IpAddr,
+    limit: u32,
+    window: Duration,
+) -> bool {
+    let now = Instant::now();
+    let mut hits = hits.lock().expect("rate limit lock poisoned");
+
+    if hits.len() >= MAX_TRACKED_ENTRIES && !hits.contains_key(&ip) {
+        hits.retain(|_, (start, _)| now.duration_since(*start) < window);
+        // Still full within one window: the oldest entries go, a tenth of
+        // the map at least so that the next scans are not on every request.
+        let keep = MAX_TRACKED_ENTRIES - MAX_TRACKED_ENTRIES / 10;
+        if hits.len() > keep {
+            let mut starts: Vec<Instant> = hits.values().map(|(start, _)| *start).collect();
+            let (_, cutoff, _) = starts.select_nth_unstable(hits.len() - keep - 1);
+            let cutoff = *cutoff;
+            hits.retain(|_, (start, _)| *start > cutoff);
+        }
+    }
+
+    let entry = hits.entry(ip).or_insert((now, 0));
+    if now.duration_since(entry.0) >= window {
+        *entry = (now, 0);
+    }
+    entry.1 += 1;
+    entry.1 <= limit
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+    bits
+}
+
+pub async fn rate_limit_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !AntiAbuse::get().check_rate_limit(addr.ip()) {
+        warn!("Rate limit atteint pour {}", addr.ip());
+        return response_err(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Trop de requêtes, veuillez réessayer plus tard".to_string(),
+        );
+    }
+
+    next.run(req).await
+}
+
+pub async fn login_rate_limit_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !AntiAbuse::get().check_login_rate_limit(addr.ip()) {
+        warn!("Rate limit de connexion atteint pour {}", addr.ip());
+        return response_err(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Trop de tentatives de connexion, veuillez réessayer plus tard".to_string(),
+        );
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,398 @@
+use axum::RequestExt;
+use axum::extract::{MatchedPath, RawPathParams, Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::Utc;
+use serde_json::{Map, Value};
+use sqlx::{Row, SqlitePool};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::helpers::suppressions::normalize_email;
+use crate::models::types::Session;
+use crate::telemetry::RequestId;
+
+/// Columns never copied into audit snapshots.
+const REDACTED_COLUMNS: [&str; 3] = ["password", "secret", "unsubscribe_token"];
+
+/// Resource written by a handler whose route does not carry its id, attached
+/// to the response so the audit log can snapshot it. `before` is set by
+/// handlers that update an existing row found from the request body.
+#[derive(Clone, Debug)]
+pub struct AuditTarget {
+    pub id: String,
+    pub before: Option<Map<String, Value>>,
+}
+
+/// Sets the `AuditTarget` of a response.
+pub fn with_target(mut response: Response, id: impl Into<String>) -> Response {
+    response.extensions_mut().insert(AuditTarget {
+        id: id.into(),
+        before: None,
+    });
+    response
+}
+
+/// Integration that authenticated a machine request, e.g. with an ingest
+/// token or a signature, recorded as the `actor_id` of its audit event.
+#[derive(Clone, Copy, Debug)]
+pub struct AuditActor(pub &'static str);
+
+/// Sets the `AuditActor` of a response.
+pub fn with_actor(mut response: Response, actor: &'static str) -> Response {
+    response.extensions_mut().insert(AuditActor(actor));
+    response
+}
+
+struct AuditedRoute {
+    method: Method,
+    route: &'static str,
+    action: &'static str,
+    /// Table snapshotted before and after the request, with the column
+    /// matching the route's path parameter or `AuditTarget`.
+    table: Option<(&'static str, &'static str)>,
+}
+
+fn audited_routes() -> [AuditedRoute; 28] {
+    let route = |method, route, action, table| AuditedRoute {
+        method,
+        route,
+        action,
+        table,
+    };
+    [
+        route(Method::POST, "/api/login", "auth.login", None),
+        route(
+            Method::POST,
+            "/api/public/subscribe",
+            "contact.subscribe",
+            Some(("contacts", "id")),
+        ),
+        route(
+            Method::PATCH,
+            "/api/public/preferences/{token}",
+            "contact.update_preferences",
+            Some(("contacts", "unsubscribe_token")),
+        ),
+        route(Method::POST, "/api/bounces", "bounce.ingest", None),
+        route(Method::POST, "/api/complaints", "complaint.ingest", None),
+        route(
+            Method::POST,
+            "/api/contacts/sync",
+            "contact.sync",
+            Some(("contacts", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/newsletters",
+            "newsletter.create",
+            Some(("sendings", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/newsletters/{id}/send",
+            "newsletter.send",
+            Some(("sendings", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/newsletters/{id}/pause",
+            "newsletter.pause",
+            Some(("sendings", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/newsletters/{id}/resume",
+            "newsletter.resume",
+            Some(("sendings", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/newsletters/{id}/cancel",
+            "newsletter.cancel",
+            Some(("sendings", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/newsletters/{id}/test",
+            "newsletter.test",
+            None,
+        ),
+        route(
+            Method::POST,
+            "/api/contact_lists",
+            "contact_list.create",
+            Some(("contact_lists", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/contact_lists/{id}/contacts",
+            "contact.create",
+            Some(("contacts", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/suppressions",
+            "suppression.create",
+            Some(("suppressions", "email")),
+        ),
+        route(
+            Method::POST,
+            "/api/suppressions/import",
+            "suppression.import",
+            None,
+        ),
+        route(
+            Method::DELETE,
+            "/api/suppressions/{email}",
+            "suppression.delete",
+            Some(("suppressions", "email")),
+        ),
+        route(
+            Method::POST,
+            "/api/seed_addresses",
+            "seed_address.create",
+            Some(("seed_addresses", "email")),
+        ),
+        route(
+            Method::DELETE,
+            "/api/seed_addresses/{email}",
+            "seed_address.delete",
+            Some(("seed_addresses", "email")),
+        ),
+        route(
+            Method::POST,
+            "/api/webhooks",
+            "webhook.create",
+            Some(("webhooks", "id")),
+        ),
+        route(
+            Method::PATCH,
+            "/api/webhooks/{id}",
+            "webhook.update",
+            Some(("webhooks", "id")),
+        ),
+        route(
+            Method::DELETE,
+            "/api/webhooks/{id}",
+            "webhook.delete",
+            Some(("webhooks", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/sender_identities",
+            "sender_identity.create",
+            Some(("sender_identities", "id")),
+        ),
+        route(
+            Method::PATCH,
+            "/api/sender_identities/{id}",
+            "sender_identity.update",
+            Some(("sender_identities", "id")),
+        ),
+        route(
+            Method::DELETE,
+            "/api/sender_identities/{id}",
+            "sender_identity.delete",
+            Some(("sender_identities", "id")),
+        ),
+        route(
+            Method::POST,
+            "/api/themes",
+            "theme.create",
+            Some(("themes", "id")),
+        ),
+        route(
+            Method::PATCH,
+            "/api/themes/{id}",
+            "theme.update",
+            Some(("themes", "id")),
+        ),
+        route(
+            Method::DELETE,
+            "/api/themes/{id}",
+            "theme.delete",
+            Some(("themes", "id")),
+        ),
+    ]
+}
+
+/// Current row of `table` as a JSON object, sensitive columns left out.
+pub async fn snapshot(
+    pool: &SqlitePool,
+    table: &str,
+    key_column: &str,
+    key: &str,
+) -> Result<Option<Map<String, Value>>, sqlx::Error> {
+    let columns: Vec<String> = sqlx::query(&format!("pragma table_info({})", table))
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get::<String, _>("name"))
+        .collect();
+    let fields = columns
+        .iter()
+        .filter(|column| !REDACTED_COLUMNS.contains(&column.as_str()))
+        .map(|column| format!("'{0}', {0}", column))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let row = sqlx::query(&format!(
+        "select json_object({}) as snapshot from {} where {} = ?",
+        fields, table, key_column
+    ))
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row
+        .and_then(|row| serde_json::from_str(&row.get::<String, _>("snapshot")).ok())
+        .and_then(|value: Value| match value {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }))
+}
+
+/// Fields whose value differs between the two snapshots, as `{"from", "to"}`.
+fn diff(
+    before: Option<&Map<String, Value>>,
+    after: Option<&Map<String, Value>>,
+) -> Map<String, Value> {
+    let empty = Map::new();
+    let (before, after) = (before.unwrap_or(&empty), after.unwrap_or(&empty));
+    before
+        .keys()
+        .chain(after.keys().filter(|key| !before.contains_key(*key)))
+        .filter(|key| {
+            before.get(*key).unwrap_or(&Value::Null) != after.get(*key).unwrap_or(&Value::Null)
+        })
+        .filter(|key| key.as_str() != "updated_at")
+        .map(|key| {
+            let change = serde_json::json!({
+                "from": before.get(key).cloned().unwrap_or(Value::Null),
+                "to": after.get(key).cloned().unwrap_or(Value::Null),
+            });
+            (key.clone(), change)
+        })
+        .collect()
+}
+
+/// Records every successful POST/PATCH/DELETE in `audit_events`, with the
+/// acting user (or the integration for machine endpoints), the request id and
+/// a before/after snapshot of the row it touched.
+pub async fn audit_middleware(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if !matches!(*req.method(), Method::POST | Method::PATCH | Method::DELETE) {
+        return next.run(req).await;
+    }
+
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().trim_end_matches('/').to_string())
+        .unwrap_or_else(|| req.uri().path().to_string());
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    let session = req.extensions().get::<Session>().cloned();
+    let path_param = req
+        .extract_parts::<RawPathParams>()
+        .await
+        .ok()
+        .and_then(|params| params.iter().next().map(|(_, value)| value.to_string()));
+
+    let audited = audited_routes()
+        .into_iter()
+        .find(|audited| audited.method == method && audited.route == route);
+    let action = audited
+        .as_ref()
+        .map(|audited| audited.action.to_string())
+        .unwrap_or_else(|| format!("{} {}", method, route));
+    let table = audited.and_then(|audited| audited.table);
+
+    // Suppressions are keyed by the normalized address.
+    let path_param = match table {
+        Some((_, "email")) => path_param.map(|email| normalize_email(&email)),
+        _ => path_param,
+    };
+
+    let pool = &state.db_pool;
+    let mut before = None;
+    if let (Some((table, key_column)), Some(key)) = (table, &path_param) {
+        match snapshot(pool, table, key_column, key).await {
+            Ok(row) => before = row,
+            Err(e) => error!("Erreur de capture de l'état avant {}: {:?}", action, e),
+        }
+    }
+
+    let response = next.run(req).await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let target = response.extensions().get::<AuditTarget>().cloned();
+    let actor = response.extensions().get::<AuditActor>().copied();
+    if let Some(target_before) = target.as_ref().and_then(|target| target.before.clone()) {
+        before = Some(target_before);
+    }
+    let created_id = target.map(|target| target.id);
+    let mut after = None;
+    if let (Some((table, key_column)), Some(key)) =
+        (table, created_id.as_ref().or(path_param.as_ref()))
+    {
+        match snapshot(pool, table, key_column, key).await {
+            Ok(row) => after = row,
+            Err(e) => error!("Erreur de capture de l'état après {}: {:?}", action, e),
+        }
+    }
+
+    // A redacted key such as a preferences token is not stored as the target.
+    let key_is_redacted = table.is_some_and(|(_, key)| REDACTED_COLUMNS.contains(&key));
+    let target_id = after
+        .as_ref()
+        .or(before.as_ref())
+        .and_then(|row| row.get("id").or_else(|| row.get("email")))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or(created_id)
+        .or(path_param.filter(|_| !key_is_redacted));
+    let changes = diff(before.as_ref(), after.as_ref());
+    let (actor_type, actor_id, actor_email) = match session {
+        Some(session) => ("user", Some(session.user_id), Some(session.user_email)),
+        None if route.starts_with("/api/public") || route == "/api/login" => {
+            ("anonymous", None, None)
+        }
+        None => ("api_key", actor.map(|AuditActor(id)| id.to_string()), None),
+    };
+
+    if let Err(e) = sqlx::query(
+        "insert into audit_events (id, request_id, actor_type, actor_id, actor_email, action, method, route, target_type, target_id, before, after, changes, status_code, created_at)
+         values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(request_id)
+    .bind(actor_type)
+    .bind(actor_id)
+    .bind(actor_email)
+    .bind(&action)
+    .bind(method.as_str())
+    .bind(&route)
+    .bind(table.map(|(table, _)| table))
+    .bind(target_id)
+    .bind(before.map(|row| Value::Object(row).to_string()))
+    .bind(after.map(|row| Value::Object(row).to_string()))
+    .bind(Value::Object(changes).to_string())
+    .bind(response.status().as_u16())
+    .bind(Utc::now())
+    .execute(pool)
+    .await
+    {
+        error!("Erreur d'enregistrement de l'audit {}: {:?}", action, e);
+    }
+
+    response
+}
@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::Utc;
+use mail_parser::{Message, MessageParser, MimeHeaders};
+use serde_json::json;
+use sqlx::{Row, SqlitePool};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::config::config::BouncesConfig;
+use crate::helpers::suppressions::{SOURCE_BOUNCE, SOURCE_COMPLAINT, normalize_email, suppress};
+use crate::helpers::webhooks::{self, EVENT_BOUNCED};
+
+/// Headers of a bounce that may carry the VERP envelope it was sent to.
+const VERP_HEADERS: [&str; 4] = ["Delivered-To", "X-Original-To", "Envelope-To", "To"];
+/// Headers of the original message quoted in a complaint that may carry its
+/// VERP envelope.
+const RETURN_PATH_HEADERS: [&str; 1] = ["Return-Path"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BounceKind {
+    Hard,
+    Soft,
+}
+
+impl BounceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BounceKind::Hard => "hard",
+            BounceKind::Soft => "soft",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BounceReport {
+    pub recipient: String,
+    pub kind: BounceKind,
+    pub status_code: Option<String>,
+    pub diagnostic: Option<String>,
+}
+
+/// The parts of an RFC 3464 delivery status notification we care about.
+#[derive(Debug, Default)]
+pub struct DeliveryStatusNotification {
+    pub reports: Vec<BounceReport>,
+    pub verp_delivery_id: Option<String>,
+    pub original_message_id: Option<String>,
+    /// Message-ID of the notification itself.
+    pub message_id: Option<String>,
+}
+
+/// The parts of an RFC 5965 abuse report (feedback loop) we care about.
+#[derive(Debug, Default)]
+pub struct FeedbackReport {
+    pub recipient: Option<String>,
+    pub feedback_type: String,
+    pub user_agent: Option<String>,
+    pub verp_delivery_id: Option<String>,
+    pub original_message_id: Option<String>,
+}
+
+pub fn parse_dsn(raw: &[u8]) -> Option<DeliveryStatusNotification> {
+    let message = MessageParser::default().parse(raw)?;
+    let mut dsn = DeliveryStatusNotification {
+        verp_delivery_id: verp_delivery_id(&message, &VERP_HEADERS),
+        message_id: message.message_id().map(|id| id.to_string()),
+        ..Default::default()
+    };
+
+    for part in &message.parts {
+        let Some(content_type) = part.content_type() else {
+            continue;
+        };
+        let ctype = content_type.ctype().to_lowercase();
+        let subtype = content_type.subtype().unwrap_or_default().to_lowercase();
+
+        match (ctype.as_str(), subtype.as_str()) {
+            ("message", "delivery-status") | ("message", "global-delivery-status") => {
+                let text = String::from_utf8_lossy(part.contents());
+                dsn.reports.extend(parse_delivery_status(&text));
+            }
+            ("message", "rfc822") | ("message", "global") if dsn.original_message_id.is_none() => {
+                dsn.original_message_id = part
+                    .message()
+                    .and_then(|m| m.message_id())
+                    .map(|id| id.to_string());
+            }
+            ("text", "rfc822-headers") if dsn.original_message_id.is_none() => {
+                dsn.original_message_id = MessageParser::default()
+                    .parse_headers(part.contents())
+                    .and_then(|m| m.message_id().map(|id| id.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    if dsn.reports.is_empty() {
+        None
+    } else {
+        Some(dsn)
+    }
+}
+
+pub fn parse_arf(raw: &[u8]) -> Option<FeedbackReport> {
+    let message = MessageParser::default().parse(raw)?;
+    let mut report: Option<FeedbackReport> = None;
+    let mut original: Option<Message> = None;
+
+    for part in &message.parts {
+        let Some(content_type) = part.content_type() else {
+            continue;
+        };
+        let ctype = content_type.ctype().to_lowercase();
+        let subtype = content_type.subtype().unwrap_or_default().to_lowercase();
+
+        match (ctype.as_str(), subtype.as_str()) {
+            ("message", "feedback-report") => {
+                let fields = parse_fields(&String::from_utf8_lossy(part.contents()));
+                report = Some(FeedbackReport {
+                    recipient: fields
+                        .get("original-rcpt-to")
+                        .map(|value| normalize_email(&strip_address_type(value))),
+                    feedback_type: fields
+                        .get("feedback-type")
+                        .map(|value| value.to_lowercase())
+                        .unwrap_or_else(|| "abuse".to_string()),
+                    user_agent: fields.get("user-agent").cloned(),
+                    ..Default::default()
+                });
+            }
+            ("message", "rfc822") | ("message", "global") if original.is_none() => {
+                original = part.message().cloned();
+            }
+            ("text", "rfc822-headers") if original.is_none() => {
+                original = MessageParser::default()
+                    .parse_headers(part.contents())
+                    .map(|m| m.into_owned());
+            }
+            _ => {}
+        }
+    }
+
+    let mut report = report?;
+    if let Some(original) = &original {
+        report.verp_delivery_id = verp_delivery_id(original, &RETURN_PATH_HEADERS);
+        report.original_message_id = original.message_id().map(|id| id.to_string());
+        if report.recipient.is_none() {
+            report.recipient = original
+                .to()
+                .and_then(|to| to.first())
+                .and_then(|addr| addr.address())
+                .map(normalize_email);
+        }
+    }
+    Some(report)
+}
+
+/// Parses the body of a `message/delivery-status` part: a per-message block
+/// followed by one block of fields per recipient, separated by blank lines.
+fn parse_delivery_status(text: &str) -> Vec<BounceReport> {
+    let normalized = text.replace("\r\n", "\n");
+    normalized
+        .split("\n\n")
+        .skip(1)
+        .map(parse_fields)
+        .filter_map(|fields| {
+            let recipient = fields
+                .get("final-recipient")
+                .or_else(|| fields.get("original-recipient"))
+                .map(|value| strip_address_type(value))?;
+            let status_code = fields.get("status").map(|s| s.trim().to_string());
+            let kind = classify(
+                fields.get("action").map(String::as_str).unwrap_or_default(),
+                status_code.as_deref(),
+            )?;
+            Some(BounceReport {
+                recipient: normalize_email(&recipient),
+                kind,
+                status_code,
+                diagnostic: fields
+                    .get("diagnostic-code")
+                    .map(|value| strip_address_type(value)),
+            })
+        })
+        .collect()
+}
+
+fn parse_fields(block: &str) -> HashMap<String, String> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut last_key: Option<String> = None;
+
+    for line in block.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = last_key.as_ref().and_then(|key| fields.get_mut(key)) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim().to_lowercase();
+            fields.insert(key.clone(), value.trim().to_string());
+            last_key = Some(key);
+        }
+    }
+    fields
+}
+
+/// `rfc822; user@example.com` -> `user@example.com`
+fn strip_address_type(value: &str) -> String {
+    value
+        .split_once(';')
+        .map(|(_, rest)| rest)
+        .unwrap_or(value)
+        .trim()
+        .to_string()
+}
+
+/// Permanent failures (5.x.x) are hard bounces, except a full mailbox which
+/// is usually temporary. Delays and 4.x.x statuses are soft bounces.
+fn classify(action: &str, status: Option<&str>) -> Option<BounceKind> {
+    let action = action.trim().to_lowercase();
+    if action != "failed" && action != "delayed" {
+        return None;
+    }
+    match status {
+        Some("5.2.2") => Some(BounceKind::Soft),
+        Some(code) if code.starts_with('5') && action == "failed" => Some(BounceKind::Hard),
+        _ => Some(BounceKind::Soft),
+    }
+}
+
+/// Delivery id encoded in a VERP address such as `bounces+<delivery-id>@domain`.
+fn verp_delivery_id(message: &Message, headers: &[&str]) -> Option<String> {
+    headers.iter().find_map(|header| {
+        let value = message.header_raw(*header)?;
+        let address = value
+            .trim()
+            .trim_start_matches('<')
+            .split(['>', ',', ' '])
+            .next()?;
+        let (local, _) = address.split_once('@')?;
+        let (_, tag) = local.split_once('+')?;
+        (!tag.is_empty()).then(|| tag.to_string())
+    })
+}
+
+async fn find_delivery(
+    pool: &SqlitePool,
+    verp_delivery_id: Option<&str>,
+    original_message_id: Option<&str>,
+    recipient: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    if let Some(delivery_id) = verp_delivery_id {
+        let row = sqlx::query("select id from deliveries where id = ?")
+            .bind(delivery_id)
+            .fetch_optional(pool)
+            .await?;
+        if let Some(row) = row {
+            return Ok(Some(row.get("id")));
+        }
+    }
+
+    if let Some(message_id) = original_message_id {
+        let row =
+            sqlx::query("select id from deliveries where message_id = ? and lower(email) = ?")
+                .bind(format!("<{}>", message_id.trim_matches(['<', '>'])))
+                .bind(recipient)
+                .fetch_optional(pool)
+                .await?;
+        if let Some(row) = row {
+            return Ok(Some(row.get("id")));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Stores a bounce, flags its delivery and suppresses the address on a hard
+/// bounce or once soft bounces reach `soft_bounce_threshold` within
+/// `soft_bounce_window_days`.
+///
+/// A soft bounce already recorded for the same delivery, status and
+/// notification is ignored, so a DSN received twice is only counted once.
+/// Returns whether the bounce was recorded.
+pub async fn record_bounce(
+    pool: &SqlitePool,
+    config: &BouncesConfig,
+    delivery_id: Option<&str>,
+    dsn_message_id: Option<&str>,
+    report: &BounceReport,
+) -> Result<bool, sqlx::Error> {
+    if report.kind == BounceKind::Soft && delivery_id.is_some() {
+        let duplicate = sqlx::query(
+            "select 1 from bounces
+             where kind = 'soft' and delivery_id = ? and status_code is ? and dsn_message_id is ?",
+        )
+        .bind(delivery_id)
+        .bind(&report.status_code)
+        .bind(dsn_message_id)
+        .fetch_optional(pool)
+        .await?;
+        if duplicate.is_some() {
+            info!(
+                "Bounce temporaire déjà enregistré pour {}, ignoré",
+                report.recipient
+            );
+            return Ok(false);
+        }
+    }
+
+    sqlx::query(
+        "insert into bounces (id, delivery_id, email, kind, status_code, diagnostic, dsn_message_id, created_at)
+         values (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(delivery_id)
+    .bind(&report.recipient)
+    .bind(report.kind.as_str())
+    .bind(&report.status_code)
+    .bind(&report.diagnostic)
+    .bind(dsn_message_id)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    let mut event_data = json!({ "email": report.recipient });
+    if let Some(delivery_id) = delivery_id {
+        // A recipient refused by the relay already has its final status.
+        sqlx::query(
+            "update deliveries set status = 'bounced', updated_at = ? where id = ? and status <> 'bounced'",
+        )
+            .bind(Utc::now())
+            .bind(delivery_id)
+            .execute(pool)
+            .await?;
+        event_data = webhooks::delivery_event_data(pool, delivery_id).await?;
+    }
+    event_data["kind"] = json!(report.kind.as_str());
+    event_data["status_code"] = json!(report.status_code);
+    event_data["diagnostic"] = json!(report.diagnostic);
+    webhooks::emit(pool, EVENT_BOUNCED, event_data).await;
+
+    let reason = match report.kind {
+        BounceKind::Hard => Some("hard_bounce"),
+        BounceKind::Soft => {
+            let since = Utc::now() - chrono::Duration::days(config.soft_bounce_window_days.into());
+            let count: i64 = sqlx::query(
+                "select count(*) as count from bounces where email = ? and kind = 'soft' and created_at >= ?",
+            )
+            .bind(&report.recipient)
+            .bind(since)
+            .fetch_one(pool)
+            .await?
+            .get("count");
+            (count >= config.soft_bounce_threshold.into()).then_some("soft_bounce")
+        }
+    };
+
+    if let Some(reason) = reason
+        && suppress(pool, &report.recipient, reason, SOURCE_BOUNCE).await?
+    {
+        info!(
+            "Adresse {} ajoutée à la liste de suppression ({})",
+            report.recipient, reason
+        );
+    }
+
+    Ok(true)
+}
+
+/// Parses a raw bounce message and records every failed recipient it reports.
+/// Returns the number of bounces recorded, `None` when the message is not a DSN.
+pub async fn process_bounce(
+    pool: &SqlitePool,
+    config: &BouncesConfig,
+    raw: &[u8],
+) -> Result<Option<usize>, sqlx::Error> {
+    let Some(dsn) = parse_dsn(raw) else {
+        return Ok(None);
+    };
+
+    let mut recorded = 0;
+    for report in &dsn.reports {
+        let delivery_id = find_delivery(
+            pool,
+            dsn.verp_delivery_id.as_deref(),
+            dsn.original_message_id.as_deref(),
+            &report.recipient,
+        )
+        .await?;
+        if delivery_id.is_none() {
+            warn!("Bounce sans remise associée pour {}", report.recipient);
+        }
+        if record_bounce(
+            pool,
+            config,
+            delivery_id.as_deref(),
+            dsn.message_id.as_deref(),
+            report,
+        )
+        .await?
+        {
+            recorded += 1;
+        }
+    }
+
+    Ok(Some(recorded))
+}
+
+/// Stores a complaint from a feedback loop and suppresses the address.
+pub async fn record_complaint(
+    pool: &SqlitePool,
+    delivery_id: Option<&str>,
+    recipient: &str,
+    report: &FeedbackReport,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "insert into complaints (id, delivery_id, email, feedback_type, user_agent, created_at)
+         values (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(delivery_id)
+    .bind(recipient)
+    .bind(&report.feedback_type)
+    .bind(&report.user_agent)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    if suppress(pool, recipient, "complaint", SOURCE_COMPLAINT).await? {
+        info!(
+            "Adresse {} ajoutée à la liste de suppression (complaint)",
+            recipient
+        );
+    }
+    Ok(())
+}
+
+/// Parses a raw abuse report and records the complaint it carries.
+/// Returns the address complained about, `None` when the message is not an
+/// abuse report or does not name the recipient.
+pub async fn process_complaint(
+    pool: &SqlitePool,
+    raw: &[u8],
+) -> Result<Option<String>, sqlx::Error> {
+    let Some(report) = parse_arf(raw) else {
+        return Ok(None);
+    };
+    let Some(recipient) = report.recipient.clone() else {
+        warn!("Plainte sans destinataire identifiable");
+        return Ok(None);
+    };
+
+    let delivery_id = find_delivery(
+        pool,
+        report.verp_delivery_id.as_deref(),
+        report.original_message_id.as_deref(),
+        &recipient,
+    )
+    .await?;
+    if delivery_id.is_none() {
+        warn!("Plainte sans remise associée pour {}", recipient);
+    }
+    record_complaint(pool, delivery_id.as_deref(), &recipient, &report).await?;
+    Ok(Some(recipient))
+}
+
+/// Processes the messages waiting in `new/` and moves them to `cur/`.
+async fn process_maildir(
+    pool: &SqlitePool,
+    config: &BouncesConfig,
+    maildir: &Path,
+) -> std::io::Result<()> {
+    let mut entries = tokio::fs::read_dir(maildir.join("new")).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let raw = tokio::fs::read(&path).await?;
+
+        let processed = match process_bounce(pool, config, &raw).await {
+            Ok(None) => process_complaint(pool, &raw)
+                .await
+                .map(|complaint| complaint.map(|email| format!("plainte pour {}", email))),
+            other => other.map(|count| count.map(|count| format!("{} bounce(s)", count))),
+        };
+        match processed {
+            Ok(Some(summary)) => info!("{} traité(s) depuis {:?}", summary, path),
+            Ok(None) => warn!(
+                "Message ignoré, ni rapport de remise ni plainte: {:?}",
+                path
+            ),
+            Err(e) => {
+                // Left in new/ so it is retried on the next pass.
+                error!("Erreur de traitement du bounce {:?}: {:?}", path, e);
+                continue;
+            }
+        }
+
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        tokio::fs::rename(
+            &path,
+            maildir.join("cur").join(format!("{}:2,S", file_name)),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+pub fn spawn_maildir_watcher(pool: SqlitePool, config: &'static BouncesConfig) {
+    let Some(maildir) = config.maildir_path.as_deref() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = process_maildir(&pool, config, maildir).await {
+                error!("Erreur de lecture du Maildir {:?}: {:?}", maildir, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DSN: &str = "From: MAILER-DAEMON@mx.example.com\r
+To: bounces+d1@nouvelles-lettres.com\r
+Subject: Undelivered Mail Returned to Sender\r
+Message-ID: <dsn1@mx.example.com>\r
+MIME-Version: 1.0\r
+Content-Type: multipart/report; report-type=delivery-status; boundary=\"B\"\r
+\r
+--B\r
+Content-Type: text/plain\r
+\r
+This is the mail system.\r
+\r
+--B\r
+Content-Type: message/delivery-status\r
+\r
+Reporting-MTA: dns; mx.example.com\r
+\r
+Final-Recipient: rfc822; user@example.com\r
+Action: failed\r
+Status: 5.1.1\r
+Diagnostic-Code: smtp; 550 5.1.1 <user@example.com>: Recipient address\r
+    rejected: User unknown\r
+\r
+Final-Recipient: rfc822; Full@Example.com\r
+Action: failed\r
+Status: 5.2.2\r
+\r
+Final-Recipient: rfc822; later@example.com\r
+Action: delayed\r
+Status: 4.4.1\r
+\r
+Final-Recipient: rfc822; ok@example.com\r
+Action: delivered\r
+Status: 2.0.0\r
+\r
+--B\r
+Content-Type: text/rfc822-headers\r
+\r
+From: nouvelle lettre <support@nouvelles-lettres.com>\r
+To: user@example.com\r
+Message-ID: <d1@nouvelles-lettres.com>\r
+\r
+--B--\r
+";
+
+    #[test]
+    fn classify_failures_and_delays() {
+        assert_eq!(classify("failed", Some("5.1.1")), Some(BounceKind::Hard));
+        assert_eq!(classify("Failed", Some("5.7.1")), Some(BounceKind::Hard));
+        // A full mailbox is usually temporary.
+        assert_eq!(classify("failed", Some("5.2.2")), Some(BounceKind::Soft));
+        assert_eq!(classify("failed", Some("4.4.7")), Some(BounceKind::Soft));
+        assert_eq!(classify("failed", None), Some(BounceKind::Soft));
+        assert_eq!(classify("delayed", Some("4.4.1")), Some(BounceKind::Soft));
+        assert_eq!(classify("delayed", Some("5.1.1")), Some(BounceKind::Soft));
+        assert_eq!(classify("delivered", Some("2.0.0")), None);
+        assert_eq!(classify("relayed", Some("2.0.0")), None);
+    }
+
+    #[test]
+    fn dsn_reports_each_failed_recipient() {
+        let dsn = parse_dsn(DSN.as_bytes()).expect("DSN not recognized");
+        assert_eq!(dsn.verp_delivery_id.as_deref(), Some("d1"));
+        assert_eq!(
+            dsn.original_message_id.as_deref(),
+            Some("d1@nouvelles-lettres.com")
+        );
+        assert_eq!(dsn.message_id.as_deref(), Some("dsn1@mx.example.com"));
+
+        let reports: Vec<(&str, BounceKind, Option<&str>)> = dsn
+            .reports
+            .iter()
+            .map(|report| {
+                (
+                    report.recipient.as_str(),
+                    report.kind,
+                    report.status_code.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            reports,
+            [
+                ("user@example.com", BounceKind::Hard, Some("5.1.1")),
+                ("full@example.com", BounceKind::Soft, Some("5.2.2")),
+                ("later@example.com", BounceKind::Soft, Some("4.4.1")),
+            ]
+        );
+        assert_eq!(
+            dsn.reports[0].diagnostic.as_deref(),
+            Some("550 5.1.1 <user@example.com>: Recipient address rejected: User unknown")
+        );
+    }
+
+    #[test]
+    fn message_without_delivery_status_is_not_a_dsn() {
+        let raw = "From: someone@example.com\r\nSubject: Hello\r\n\r\nHi\r\n";
+        assert!(parse_dsn(raw.as_bytes()).is_none());
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use lettre::message::dkim::{
+    DkimCanonicalization, DkimCanonicalizationType, DkimConfig as SigningConfig,
+    DkimSigningAlgorithm, DkimSigningKey,
+};
+use lettre::message::header::HeaderName;
+use rand::Rng;
+use rsa::pkcs1::{EncodeRsaPrivateKey, LineEnding};
+use rsa::pkcs8::EncodePublicKey;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+
+use crate::config::config::{DkimAlgorithm, DkimConfig};
+
+const RSA_KEY_BITS: usize = 2048;
+/// Headers covered by the signature. Content-Type is left out, lettre only
+/// writes it after signing for multipart messages.
+const SIGNED_HEADERS: [&str; 6] = ["From", "Reply-To", "To", "Subject", "Date", "Message-ID"];
+
+/// Loads the private key of `config`, panicking when it cannot be used.
+pub fn signing_config(config: &DkimConfig) -> SigningConfig {
+    let private_key =
+        fs::read_to_string(&config.private_key_path).expect("Failed to read DKIM private key");
+    let algorithm = match config.algorithm {
+        DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+    };
+    let key = DkimSigningKey::new(private_key.trim(), algorithm).expect("Invalid DKIM private key");
+
+    SigningConfig::new(
+        config.selector.clone(),
+        config.domain.clone(),
+        key,
+        SIGNED_HEADERS
+            .iter()
+            .map(|name| HeaderName::new_from_ascii_str(name))
+            .collect(),
+        DkimCanonicalization {
+            header: DkimCanonicalizationType::Relaxed,
+            body: DkimCanonicalizationType::Relaxed,
+        },
+    )
+}
+
+/// Generates a key pair, returned as the private key in the format
+/// `signing_config` reads and the base64 public key for the DNS record.
+pub fn generate_key(
+    algorithm: DkimAlgorithm,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    match algorithm {
+        DkimAlgorithm::Rsa => {
+            let private_key = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, RSA_KEY_BITS)?;
+            let public_key = RsaPublicKey::from(&private_key).to_public_key_der()?;
+            Ok((
+                private_key.to_pkcs1_pem(LineEnding::LF)?.to_string(),
+                STANDARD.encode(public_key.as_bytes()),
+            ))
+        }
+        DkimAlgorithm::Ed25519 => {
+            let seed: [u8; 32] = rand::rng().random();
+            let public_key = ed25519_dalek::SigningKey::from_bytes(&seed).verifying_key();
+            Ok((
+                format!("{}\n", STANDARD.encode(seed)),
+                STANDARD.encode(public_key.as_bytes()),
+            ))
+        }
+    }
+}
+
+/// The TXT record publishing `public_key`, its value split in strings of at
+/// most 255 characters as DNS requires.
+pub fn dns_record(
+    selector: &str,
+    domain: &str,
+    algorithm: DkimAlgorithm,
+    public_key: &str,
+) -> String {
+    let key_type = match algorithm {
+        DkimAlgorithm::Rsa => "rsa",
+        DkimAlgorithm::Ed25519 => "ed25519",
+    };
+    let value = format!("v=DKIM1; k={}; p={}", key_type, public_key);
+    let strings: Vec<String> = value
+        .as_bytes()
+        .chunks(255)
+        .map(|chunk| format!("\"{}\"", String::from_utf8_lossy(chunk)))
+        .collect();
+    format!(
+        "{}._domainkey.{}. IN TXT {}",
+        selector,
+        domain,
+        strings.join(" ")
+    )
+}
@@ -1,19 +1,385 @@
-use std::sync::OnceLock;
-use std::time::Duration;
+use std::fmt;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-use crate::config::config::EmailConfig;
-use lettre::message::{Mailbox, header};
-use lettre::transport::smtp::client::{Tls, TlsParameters};
-use lettre::{Message, SmtpTransport, Transport, transport::smtp::authentication::Credentials};
-use tracing::error;
+use crate::config::config::{EmailConfig, SmtpConfig};
+use crate::helpers::dkim;
+use crate::models::relays::RelayStats;
+use chrono::{DateTime, Utc};
+use lettre::Message;
+use lettre::address::{Address, AddressError, Envelope};
+use lettre::message::dkim::DkimConfig;
+use lettre::message::{Mailbox, MultiPart, header};
+use lettre::transport::smtp::authentication::{Credentials, DEFAULT_MECHANISMS};
+use lettre::transport::smtp::client::{SmtpConnection, Tls, TlsParameters};
+use lettre::transport::smtp::commands::{Data, Mail, Rcpt};
+use lettre::transport::smtp::extension::{ClientId, Extension, MailBodyParameter, MailParameter};
+use rand::Rng;
+use regex::Regex;
+use tracing::{error, warn};
 
 static EMAIL_CONFIG: OnceLock<Email> = OnceLock::new();
 const SMTP_TIMEOUT: Duration = Duration::from_secs(6);
+/// Consecutive connection failures after which a relay is taken out of rotation.
+const RELAY_DOWN_AFTER: u32 = 3;
+const RELAY_COOLDOWN: Duration = Duration::from_secs(60);
+/// Open connections kept per relay between two messages.
+const MAX_IDLE_CONNECTIONS: usize = 4;
 
+/// RFC 3463 enhanced status code in the text of a reply, e.g. `5.1.1`.
+static ENHANCED_CODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b([245]\.\d{1,3}\.\d{1,3})\b").expect("Invalid enhanced status regex")
+});
+
+/// Step of the SMTP conversation a failure happened at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpStage {
+    /// Connection, greeting, EHLO, STARTTLS and authentication.
+    Connect,
+    MailFrom,
+    Recipient,
+    Data,
+}
+
+/// A failed send. Permanent failures are 5xx replies; 4xx replies, timeouts
+/// and connection errors are worth trying again.
+#[derive(Debug)]
+pub struct SendFailure {
+    pub permanent: bool,
+    /// SMTP reply code, when the server answered.
+    pub smtp_code: Option<String>,
+    pub message: String,
+    /// Relay of the last attempt.
+    pub relay: Option<String>,
+    /// Where the SMTP conversation failed, `None` when it did not take place.
+    pub stage: Option<SmtpStage>,
+}
+
+/// A message that cannot be built for its recipient.
+#[derive(Debug)]
+pub enum MessageError {
+    /// The recipient's address is not valid.
+    Recipient(AddressError),
+    /// The VERP address made for the delivery is not valid.
+    EnvelopeSender(AddressError),
+    /// The headers or body were refused, or no envelope could be made.
+    Build(lettre::error::Error),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::Recipient(e) => write!(f, "Adresse du destinataire invalide: {}", e),
+            MessageError::EnvelopeSender(e) => write!(f, "Adresse VERP invalide: {}", e),
+            MessageError::Build(e) => write!(f, "Message invalide: {}", e),
+        }
+    }
+}
+
+impl From<MessageError> for SendFailure {
+    fn from(err: MessageError) -> Self {
+        Self {
+            permanent: true,
+            smtp_code: None,
+            message: err.to_string(),
+            relay: None,
+            stage: None,
+        }
+    }
+}
+
+impl SendFailure {
+    pub fn transient(message: String) -> Self {
+        Self {
+            permanent: false,
+            smtp_code: None,
+            message,
+            relay: None,
+            stage: None,
+        }
+    }
+
+    fn at(stage: SmtpStage) -> impl FnOnce(lettre::transport::smtp::Error) -> Self {
+        move |err| Self {
+            stage: Some(stage),
+            ..err.into()
+        }
+    }
+
+    /// Whether the message could not be built for the recipient, which no
+    /// other attempt or relay would change.
+    pub fn message_invalid(&self) -> bool {
+        self.permanent && self.stage.is_none()
+    }
+
+    /// Enhanced status code given with the reply, if any.
+    pub fn enhanced_code(&self) -> Option<&str> {
+        self.smtp_code.as_ref()?;
+        ENHANCED_CODE
+            .captures(&self.message)
+            .and_then(|captures| captures.get(1))
+            .map(|code| code.as_str())
+    }
+
+    /// Whether the relay refused the recipient itself, which is a bounce:
+    /// a 550/551/553 reply to `RCPT TO`, or a 5.1.x (addressing) or 5.2.x
+    /// (mailbox) enhanced status once the sender was accepted. Other
+    /// permanent failures (authentication, sender refused...) are on our side.
+    pub fn recipient_rejected(&self) -> bool {
+        if !self.permanent || !matches!(self.stage, Some(SmtpStage::Recipient | SmtpStage::Data)) {
+            return false;
+        }
+        // 5.1.7 and 5.1.8 are about the sender's address.
+        let enhanced_code = self.enhanced_code();
+        let sender_status = matches!(enhanced_code, Some("5.1.7" | "5.1.8"));
+        let rcpt_refused = self.stage == Some(SmtpStage::Recipient)
+            && matches!(self.smtp_code.as_deref(), Some("550" | "551" | "553"))
+            && !sender_status;
+        let mailbox_status = enhanced_code.is_some_and(|code| {
+            (code.starts_with("5.1.") && !sender_status) || code.starts_with("5.2.")
+        });
+        rcpt_refused || mailbox_status
+    }
+
+    /// Whether the relay itself is at fault rather than the message, so that
+    /// the next relay is tried: no reply at all, a failure before the
+    /// transaction (greeting, STARTTLS, authentication), `421 Service not
+    /// available`, or a refusal of the relay to serve us. A connection lost
+    /// during `DATA` is not: the relay may have accepted the message, sending
+    /// it through another one could deliver it twice.
+    fn relay_unavailable(&self) -> bool {
+        if self.stage == Some(SmtpStage::Connect) || self.relay_refused() {
+            return true;
+        }
+        match self.smtp_code.as_deref() {
+            Some(code) => code == "421",
+            None => self.stage != Some(SmtpStage::Data),
+        }
+    }
+
+    /// Whether the relay turned us down: TLS or authentication failures
+    /// (454, 530, 535) and `554` outside of the message content. Waiting
+    /// does not fix these, the relay is taken out of rotation right away.
+    fn relay_refused(&self) -> bool {
+        if self.recipient_rejected() {
+            return false;
+        }
+        match self.smtp_code.as_deref() {
+            Some("454" | "530" | "535") => true,
+            Some("554") => self.stage != Some(SmtpStage::Data),
+            Some(_) => self.permanent && self.stage == Some(SmtpStage::Connect),
+            None => false,
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SendFailure {
+    fn from(err: lettre::transport::smtp::Error) -> Self {
+        Self {
+            permanent: err.is_permanent(),
+            smtp_code: err.status().map(|code| code.to_string()),
+            message: err.to_string(),
+            relay: None,
+            stage: None,
+        }
+    }
+}
+
+/// Connections to a relay waiting for the next message.
+#[derive(Default)]
+struct IdleConnections(Mutex<Vec<SmtpConnection>>);
+
+impl fmt::Debug for IdleConnections {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.0.lock().map(|idle| idle.len()).unwrap_or_default();
+        f.debug_tuple("IdleConnections").field(&count).finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct RelayHealth {
+    attempts: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    down_until: Option<Instant>,
+    last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct Relay {
+    name: String,
+    host: String,
+    port: u16,
+    priority: u32,
+    weight: u32,
+    tls: Tls,
+    credentials: Credentials,
+    idle: IdleConnections,
+    health: Mutex<RelayHealth>,
+}
+
+impl Relay {
+    fn is_down(&self, now: Instant) -> bool {
+        let health = self.health.lock().expect("Relay health lock poisoned");
+        health.down_until.is_some_and(|until| until > now)
+    }
+
+    /// Opens an authenticated connection.
+    fn connect(&self) -> Result<SmtpConnection, SendFailure> {
+        let hello_name = ClientId::default();
+        let wrapper = match &self.tls {
+            Tls::Wrapper(parameters) => Some(parameters),
+            _ => None,
+        };
+        let mut conn = SmtpConnection::connect(
+            (self.host.as_str(), self.port),
+            Some(SMTP_TIMEOUT),
+            &hello_name,
+            wrapper,
+            None,
+        )
+        .map_err(SendFailure::at(SmtpStage::Connect))?;
+
+        let mut setup = || {
+            if let Tls::Required(parameters) = &self.tls {
+                conn.starttls(parameters, &hello_name)?;
+            }
+            conn.auth(DEFAULT_MECHANISMS, &self.credentials)
+        };
+        if let Err(err) = setup() {
+            conn.abort();
+            return Err(SendFailure::at(SmtpStage::Connect)(err));
+        }
+        Ok(conn)
+    }
+
+    /// Sends a message on an idle connection, or a new one.
+    fn send(&self, message: &Message) -> Result<(), SendFailure> {
+        let mut conn = loop {
+            let idle = self.idle.0.lock().expect("Relay pool lock poisoned").pop();
+            match idle {
+                Some(mut conn) => {
+                    if !conn.has_broken() && conn.test_connected() {
+                        break conn;
+                    }
+                    conn.abort();
+                }
+                None => break self.connect()?,
+            }
+        };
+
+        match transaction(&mut conn, message) {
+            Ok(()) => {
+                let mut idle = self.idle.0.lock().expect("Relay pool lock poisoned");
+                if idle.len() < MAX_IDLE_CONNECTIONS {
+                    idle.push(conn);
+                } else {
+                    let _ = conn.quit();
+                }
+                Ok(())
+            }
+            Err(failure) => {
+                conn.abort();
+                Err(failure)
+            }
+        }
+    }
+
+    fn record(&self, result: Result<(), &SendFailure>) {
+        let mut health = self.health.lock().expect("Relay health lock poisoned");
+        health.attempts += 1;
+        let Err(failure) = result else {
+            health.consecutive_failures = 0;
+            health.down_until = None;
+            return;
+        };
+        health.failures += 1;
+        health.last_error = Some(failure.message.clone());
+        health.last_error_at = Some(Utc::now());
+        if failure.relay_unavailable() {
+            health.consecutive_failures += 1;
+            if health.consecutive_failures >= RELAY_DOWN_AFTER || failure.relay_refused() {
+                if health.down_until.is_none() {
+                    warn!(
+                        "Relais SMTP {} indisponible, retiré pour {:?}",
+                        self.name, RELAY_COOLDOWN
+                    );
+                }
+                health.down_until = Some(Instant::now() + RELAY_COOLDOWN);
+            }
+        }
+    }
+}
+
+fn tls(smtp: &SmtpConfig) -> Tls {
+    let tls_parameters =
+        TlsParameters::new(smtp.server_host.clone()).expect("Failed to initialize TLS parameters");
+    if smtp.server_starttls {
+        Tls::Required(tls_parameters)
+    } else {
+        Tls::Wrapper(tls_parameters)
+    }
+}
+
+/// `MAIL FROM`, `RCPT TO` and `DATA` for one message, keeping track of the
+/// command each failure answers.
+fn transaction(conn: &mut SmtpConnection, message: &Message) -> Result<(), SendFailure> {
+    let envelope = message.envelope();
+    let raw = message.formatted();
+    let mut options = Vec::new();
+    let non_ascii_addresses = envelope
+        .from()
+        .into_iter()
+        .chain(envelope.to())
+        .any(|address| !AsRef::<str>::as_ref(address).is_ascii());
+    if non_ascii_addresses {
+        if !conn.server_info().supports_feature(Extension::SmtpUtfEight) {
+            return Err(SendFailure {
+                permanent: true,
+                stage: Some(SmtpStage::MailFrom),
+                ..SendFailure::transient("Le relais ne prend pas en charge SMTPUTF8".to_string())
+            });
+        }
+        options.push(MailParameter::SmtpUtfEight);
+    }
+    if !raw.is_ascii() {
+        if !conn.server_info().supports_feature(Extension::EightBitMime) {
+            return Err(SendFailure {
+                permanent: true,
+                stage: Some(SmtpStage::MailFrom),
+                ..SendFailure::transient("Le relais ne prend pas en charge 8BITMIME".to_string())
+            });
+        }
+        options.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+    }
+
+    conn.command(Mail::new(envelope.from().cloned(), options))
+        .map_err(SendFailure::at(SmtpStage::MailFrom))?;
+    for to in envelope.to() {
+        conn.command(Rcpt::new(to.clone(), Vec::new()))
+            .map_err(SendFailure::at(SmtpStage::Recipient))?;
+    }
+    conn.command(Data)
+        .map_err(SendFailure::at(SmtpStage::Data))?;
+    conn.message(&raw)
+        .map_err(SendFailure::at(SmtpStage::Data))?;
+    Ok(())
+}
+
+/// Identity a message is sent as.
 #[derive(Debug, Clone)]
+pub struct Sender {
+    pub from: Mailbox,
+    pub reply_to: Option<Mailbox>,
+}
+
+#[derive(Debug)]
 pub struct Email {
-    mailer: SmtpTransport,
+    relays: Vec<Relay>,
     from: Mailbox,
+    dkim: Option<DkimConfig>,
+    verp: Option<(String, String)>,
 }
 
 impl Email {
@@ -29,26 +395,24 @@ impl Email {
     }
 
     pub fn new(config: &EmailConfig) -> Self {
-        let creds = Credentials::new(
-            config.smtp.auth_user.clone(),
-            config.smtp.auth_password.clone(),
-        );
-
-        let tls_parameters = TlsParameters::new(config.smtp.server_host.clone())
-            .expect("Failed to initialize TLS parameters");
-        let tls = if config.smtp.server_starttls {
-            Tls::Required(tls_parameters)
-        } else {
-            Tls::Wrapper(tls_parameters)
-        };
-
-        let mailer = SmtpTransport::relay(&config.smtp.server_host)
-            .expect("Échec de la configuration SMTP relay")
-            .port(config.smtp.server_port)
-            .credentials(creds)
-            .tls(tls)
-            .timeout(Some(SMTP_TIMEOUT))
-            .build();
+        let relays = config
+            .all_relays()
+            .into_iter()
+            .map(|relay| Relay {
+                tls: tls(&relay.smtp),
+                credentials: Credentials::new(
+                    relay.smtp.auth_user.clone(),
+                    relay.smtp.auth_password.clone(),
+                ),
+                idle: IdleConnections::default(),
+                host: relay.smtp.server_host,
+                port: relay.smtp.server_port,
+                name: relay.name,
+                priority: relay.priority,
+                weight: relay.weight,
+                health: Mutex::new(RelayHealth::default()),
+            })
+            .collect();
 
         let from = Mailbox::new(
             Some(config.identity.from_name.clone()),
@@ -59,27 +423,319 @@ impl Email {
                 .expect("Invalid email address"),
         );
 
-        Self { mailer, from }
+        let verp = config.identity.verp_address.as_ref().map(|address| {
+            let address: Address = address.parse().expect("Invalid VERP address");
+            (address.user().to_string(), address.domain().to_string())
+        });
+
+        let dkim = config.dkim.as_ref().map(dkim::signing_config);
+
+        Self {
+            relays,
+            from,
+            dkim,
+            verp,
+        }
     }
 
-    pub fn send_email(
+    /// Message-ID given to the message of a delivery, so that bounces quoting
+    /// it can be linked back to the delivery.
+    pub fn message_id(&self, delivery_id: &str) -> String {
+        format!("<{}@{}>", delivery_id, self.from.email.domain())
+    }
+
+    /// Per-delivery envelope sender (`local+<delivery-id>@domain`), when VERP is configured.
+    pub fn envelope_sender(&self, delivery_id: &str) -> Result<Option<Address>, MessageError> {
+        self.verp
+            .as_ref()
+            .map(|(local, domain)| {
+                Address::new(format!("{}+{}", local, delivery_id), domain)
+                    .map_err(MessageError::EnvelopeSender)
+            })
+            .transpose()
+    }
+
+    /// Relays in the order they should be tried: those up by priority, picking
+    /// at random by weight within a priority, then those down as a last resort.
+    fn relay_order(&self) -> Vec<&Relay> {
+        let now = Instant::now();
+        let (mut up, down): (Vec<&Relay>, Vec<&Relay>) =
+            self.relays.iter().partition(|relay| !relay.is_down(now));
+        up.sort_by_key(|relay| relay.priority);
+
+        let mut rng = rand::rng();
+        let mut order = Vec::with_capacity(self.relays.len());
+        while !up.is_empty() {
+            let priority = up[0].priority;
+            let tier = up
+                .iter()
+                .take_while(|relay| relay.priority == priority)
+                .count();
+            let total: u32 = up[..tier].iter().map(|relay| relay.weight).sum();
+            let mut pick = rng.random_range(0..total);
+            let index = up[..tier]
+                .iter()
+                .position(|relay| {
+                    if pick < relay.weight {
+                        return true;
+                    }
+                    pick -= relay.weight;
+                    false
+                })
+                .unwrap_or(0);
+            order.push(up.remove(index));
+        }
+        order.extend(down);
+        order
+    }
+
+    pub fn relay_stats(&self) -> Vec<RelayStats> {
+        let now = Instant::now();
+        self.relays
+            .iter()
+            .map(|relay| {
+                let health = relay.health.lock().expect("Relay health lock poisoned");
+                let down_for = health
+                    .down_until
+                    .and_then(|until| until.checked_duration_since(now));
+                RelayStats {
+                    name: relay.name.clone(),
+                    host: relay.host.clone(),
+                    port: relay.port,
+                    priority: relay.priority,
+                    weight: relay.weight,
+                    healthy: down_for.is_none(),
+                    down_until: down_for.and_then(|duration| {
+                        chrono::Duration::from_std(duration)
+                            .ok()
+                            .map(|duration| Utc::now() + duration)
+                    }),
+                    consecutive_failures: health.consecutive_failures,
+                    attempts: health.attempts,
+                    failures: health.failures,
+                    last_error: health.last_error.clone(),
+                    last_error_at: health.last_error_at,
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// The identity of the configuration.
+    pub fn default_sender(&self) -> Sender {
+        Sender {
+            from: self.from.clone(),
+            reply_to: None,
+        }
+    }
+
+    /// Identity of a sending, the one of the configuration when it has none
+    /// or its address cannot be used.
+    pub fn sender(
         &self,
+        from_name: Option<&str>,
+        from_email: Option<&str>,
+        reply_to: Option<&str>,
+    ) -> Sender {
+        let (Some(from_name), Some(from_email)) = (from_name, from_email) else {
+            return self.default_sender();
+        };
+        let from = match from_email.parse() {
+            Ok(address) => Mailbox::new(Some(from_name.to_string()), address),
+            Err(e) => {
+                error!("Adresse d'expéditeur invalide {}: {}", from_email, e);
+                return self.default_sender();
+            }
+        };
+        let reply_to = reply_to.and_then(|reply_to| match reply_to.parse() {
+            Ok(mailbox) => Some(mailbox),
+            Err(e) => {
+                error!("Adresse de réponse invalide {}: {}", reply_to, e);
+                None
+            }
+        });
+        Sender { from, reply_to }
+    }
+
+    /// Message of a delivery, signed when DKIM is configured.
+    ///
+    /// `body` is HTML, sent along with `text` as its alternative when given.
+    pub fn build_message(
+        &self,
+        sender: &Sender,
+        delivery_id: &str,
         to: &str,
         subject: &str,
         body: &str,
-    ) -> Result<(), lettre::transport::smtp::Error> {
-        let email = Message::builder()
-            .from(self.from.clone())
-            .to(to.parse().expect("Erreur parsing destinataire"))
+        text: Option<&str>,
+    ) -> Result<Message, MessageError> {
+        let to_address: Address = to.parse().map_err(MessageError::Recipient)?;
+        let mut builder = Message::builder()
+            .from(sender.from.clone())
+            .to(Mailbox::new(None, to_address.clone()))
             .subject(subject)
-            .header(header::ContentType::TEXT_HTML)
-            .body(body.to_string())
-            .expect("Erreur création e-mail");
+            .message_id(Some(self.message_id(delivery_id)));
+        if let Some(reply_to) = &sender.reply_to {
+            builder = builder.reply_to(reply_to.clone());
+        }
+        if let Some(envelope_sender) = self.envelope_sender(delivery_id)? {
+            builder = builder.envelope(
+                Envelope::new(Some(envelope_sender), vec![to_address])
+                    .map_err(MessageError::Build)?,
+            );
+        }
+        let mut email = match text {
+            Some(text) => builder.multipart(MultiPart::alternative_plain_html(
+                text.to_string(),
+                body.to_string(),
+            )),
+            None => builder
+                .header(header::ContentType::TEXT_HTML)
+                .body(body.to_string()),
+        }
+        .map_err(MessageError::Build)?;
+        if let Some(dkim) = &self.dkim {
+            email.sign(dkim);
+        }
+        Ok(email)
+    }
+
+    /// Sends through the first relay that is up, failing over to the next
+    /// one when a relay cannot be reached or refuses us. Returns the name of
+    /// the relay used.
+    pub fn send_email(
+        &self,
+        sender: &Sender,
+        delivery_id: &str,
+        to: &str,
+        subject: &str,
+        body: &str,
+        text: Option<&str>,
+    ) -> Result<&str, SendFailure> {
+        let email = self.build_message(sender, delivery_id, to, subject, body, text)?;
+
+        let mut last_failure = None;
+        for relay in self.relay_order() {
+            let failure = match relay.send(&email) {
+                Ok(()) => {
+                    relay.record(Ok(()));
+                    return Ok(&relay.name);
+                }
+                Err(failure) => SendFailure {
+                    relay: Some(relay.name.clone()),
+                    ..failure
+                },
+            };
+            relay.record(Err(&failure));
+            error!(
+                "Erreur lors de l'envoi à {} via {}: {}",
+                to, relay.name, failure.message
+            );
+            if !failure.relay_unavailable() {
+                return Err(failure);
+            }
+            last_failure = Some(failure);
+        }
+        Err(last_failure.expect("No SMTP relay configured"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(stage: SmtpStage, code: &str, text: &str) -> SendFailure {
+        SendFailure {
+            permanent: code.starts_with('5'),
+            smtp_code: Some(code.to_string()),
+            message: format!("{} error ({}): {}", code, code, text),
+            relay: None,
+            stage: Some(stage),
+        }
+    }
+
+    fn no_reply(stage: SmtpStage) -> SendFailure {
+        SendFailure {
+            stage: Some(stage),
+            ..SendFailure::transient("response error: incomplete response".to_string())
+        }
+    }
+
+    #[test]
+    fn rcpt_refusals_are_recipient_rejections() {
+        for code in ["550", "551", "553"] {
+            let failure = failure(SmtpStage::Recipient, code, "no such user");
+            assert!(failure.recipient_rejected(), "{} at RCPT", code);
+            assert!(!failure.relay_refused(), "{} at RCPT", code);
+            assert!(!failure.relay_unavailable(), "{} at RCPT", code);
+        }
+        assert!(failure(SmtpStage::Recipient, "550", "5.1.1 user unknown").recipient_rejected());
+        assert!(failure(SmtpStage::Recipient, "552", "5.2.2 mailbox full").recipient_rejected());
+        assert!(!failure(SmtpStage::MailFrom, "550", "no such user").recipient_rejected());
+        assert!(!failure(SmtpStage::Recipient, "450", "4.2.1 try later").recipient_rejected());
+    }
+
+    #[test]
+    fn sender_statuses_do_not_reject_the_recipient() {
+        for code in ["5.1.7", "5.1.8"] {
+            let text = format!("{} sender address rejected", code);
+            for stage in [SmtpStage::MailFrom, SmtpStage::Recipient, SmtpStage::Data] {
+                let failure = failure(stage, "550", &text);
+                assert!(!failure.recipient_rejected(), "{} at {:?}", code, stage);
+                assert!(!failure.relay_unavailable(), "{} at {:?}", code, stage);
+            }
+        }
+    }
+
+    #[test]
+    fn code_554_depends_on_the_stage() {
+        let content = failure(SmtpStage::Data, "554", "message refused");
+        assert!(!content.recipient_rejected());
+        assert!(!content.relay_refused());
+        assert!(!content.relay_unavailable());
+
+        let greeting = failure(SmtpStage::Connect, "554", "no SMTP service here");
+        assert!(!greeting.recipient_rejected());
+        assert!(greeting.relay_refused());
+        assert!(greeting.relay_unavailable());
+    }
+
+    #[test]
+    fn code_421_fails_over_without_taking_the_relay_out() {
+        for stage in [SmtpStage::MailFrom, SmtpStage::Recipient, SmtpStage::Data] {
+            let failure = failure(stage, "421", "4.3.2 service not available");
+            assert!(!failure.permanent);
+            assert!(!failure.recipient_rejected(), "421 at {:?}", stage);
+            assert!(!failure.relay_refused(), "421 at {:?}", stage);
+            assert!(failure.relay_unavailable(), "421 at {:?}", stage);
+        }
+    }
+
+    #[test]
+    fn tls_and_authentication_failures_refuse_the_relay() {
+        for code in ["454", "530", "535"] {
+            for stage in [SmtpStage::Connect, SmtpStage::MailFrom] {
+                let failure = failure(stage, code, "authentication failed");
+                assert!(!failure.recipient_rejected(), "{} at {:?}", code, stage);
+                assert!(failure.relay_refused(), "{} at {:?}", code, stage);
+                assert!(failure.relay_unavailable(), "{} at {:?}", code, stage);
+            }
+        }
+    }
 
-        if let Err(err) = self.mailer.send(&email) {
-            error!("Erreur lors de l'envoi à {}: {:?}", to, err);
-            return Err(err);
+    #[test]
+    fn lost_connection_fails_over_except_during_data() {
+        for stage in [
+            SmtpStage::Connect,
+            SmtpStage::MailFrom,
+            SmtpStage::Recipient,
+        ] {
+            assert!(no_reply(stage).relay_unavailable(), "{:?}", stage);
         }
-        Ok(())
+        let data = no_reply(SmtpStage::Data);
+        assert!(!data.permanent);
+        assert!(!data.recipient_rejected());
+        assert!(!data.relay_refused());
+        assert!(!data.relay_unavailable());
     }
 }
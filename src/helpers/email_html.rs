@@ -0,0 +1,542 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{
+    ElementContentHandlers, EndTagHandler, RewriteStrSettings, Selector, doc_text, doctype,
+    element, text,
+};
+use tracing::error;
+
+/// Elements dropped or blocked by most mail clients.
+const UNSUPPORTED_ELEMENTS: [&str; 9] = [
+    "script", "form", "iframe", "object", "embed", "video", "audio", "svg", "canvas",
+];
+/// Elements styles are never inlined into.
+const UNSTYLED_ELEMENTS: [&str; 8] = [
+    "html", "head", "meta", "title", "style", "link", "script", "base",
+];
+const META_CHARSET: &str = r#"<meta charset="utf-8">"#;
+/// Elements set apart by a blank line in the text version.
+const TEXT_BLOCK_ELEMENTS: &str = "p, h1, h2, h3, h4, h5, h6, ul, ol, table, blockquote, pre, hr";
+/// Breaks marked in the HTML before its text is collected, whitespace of the
+/// HTML itself is collapsed.
+const LINE_BREAK_CHAR: char = '\u{1}';
+const LINE_BREAK: &str = "\u{1}";
+const PARAGRAPH_BREAK_CHAR: char = '\u{2}';
+const PARAGRAPH_BREAK: &str = "\u{2}";
+const META_VIEWPORT: &str =
+    r#"<meta name="viewport" content="width=device-width, initial-scale=1">"#;
+
+/// HTML ready to be sent, with what may not display as intended.
+pub struct PreparedHtml {
+    pub html: String,
+    pub warnings: Vec<String>,
+}
+
+struct CssRule {
+    selector: Selector,
+    specificity: (u32, u32, u32),
+    declarations: String,
+}
+
+#[derive(Default)]
+struct Stylesheet {
+    rules: Vec<CssRule>,
+    /// At-rules and rules that cannot be inlined, kept in a `<style>` block.
+    retained: String,
+}
+
+#[derive(Default)]
+struct Document {
+    doctype: bool,
+    html: bool,
+    head: bool,
+    charset: bool,
+    viewport: bool,
+}
+
+fn warn(warnings: &mut Vec<String>, message: String) {
+    if !warnings.contains(&message) {
+        warnings.push(message);
+    }
+}
+
+/// Inlines the CSS of `<style>` blocks and of `stylesheet` into the style
+/// attributes, completes the document with its doctype and meta tags, and
+/// lists the constructs mail clients do not support.
+pub fn prepare(html: &str, stylesheet: Option<&str>) -> PreparedHtml {
+    let warnings = RefCell::new(Vec::new());
+    let css = RefCell::new(stylesheet.unwrap_or_default().to_string());
+    let document = RefCell::new(Document::default());
+
+    let scanned = lol_html::rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                text!("style", |chunk| {
+                    css.borrow_mut().push_str(chunk.as_str());
+                    chunk.remove();
+                    Ok(())
+                }),
+                element!("style", |el| {
+                    el.remove();
+                    Ok(())
+                }),
+                element!("*", |el| {
+                    let tag = el.tag_name();
+                    let mut document = document.borrow_mut();
+                    match tag.as_str() {
+                        "html" => document.html = true,
+                        "head" => document.head = true,
+                        "meta" if el.has_attribute("charset") => document.charset = true,
+                        "meta" if el.get_attribute("name").as_deref() == Some("viewport") => {
+                            document.viewport = true
+                        }
+                        "link" if el.get_attribute("rel").as_deref() == Some("stylesheet") => warn(
+                            &mut warnings.borrow_mut(),
+                            "Feuille de style externe <link> ignorée par les clients mail"
+                                .to_string(),
+                        ),
+                        tag if UNSUPPORTED_ELEMENTS.contains(&tag) => warn(
+                            &mut warnings.borrow_mut(),
+                            format!("Élément <{}> non supporté par les clients mail", tag),
+                        ),
+                        _ => {}
+                    }
+                    if let Some(style) = el.get_attribute("style") {
+                        check_declarations(&style, &mut warnings.borrow_mut());
+                    }
+                    Ok(())
+                }),
+            ],
+            document_content_handlers: vec![doctype!(|_| {
+                document.borrow_mut().doctype = true;
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    );
+    let scanned = match scanned {
+        Ok(scanned) => scanned,
+        Err(e) => {
+            error!("Erreur d'analyse du HTML: {:?}", e);
+            return PreparedHtml {
+                html: html.to_string(),
+                warnings: vec!["HTML invalide, envoyé sans traitement".to_string()],
+            };
+        }
+    };
+
+    let mut warnings = warnings.into_inner();
+    let mut stylesheet = parse_stylesheet(&css.into_inner(), &mut warnings);
+    let document = document.into_inner();
+    let scanned = if document.html {
+        scanned
+    } else {
+        format!("<html><head></head><body>{}</body></html>", scanned)
+    };
+
+    let mut head = String::new();
+    if !document.charset {
+        head.push_str(META_CHARSET);
+    }
+    if !document.viewport {
+        head.push_str(META_VIEWPORT);
+    }
+    let retained = stylesheet.retained.trim();
+    let style = if retained.is_empty() {
+        String::new()
+    } else {
+        format!("<style type=\"text/css\">\n{}\n</style>", retained)
+    };
+
+    // Rules are prepended from the last to the first in cascade order, so
+    // that they end up in that order, followed by the element's own style.
+    stylesheet.rules.reverse();
+    stylesheet
+        .rules
+        .sort_by_key(|rule| std::cmp::Reverse(rule.specificity));
+    let mut handlers: Vec<(Cow<Selector>, ElementContentHandlers)> = stylesheet
+        .rules
+        .iter()
+        .map(|rule| {
+            let handler = ElementContentHandlers::default().element(|el: &mut Element| {
+                if UNSTYLED_ELEMENTS.contains(&el.tag_name().as_str()) {
+                    return Ok(());
+                }
+                let style = match el.get_attribute("style") {
+                    Some(style) if !style.trim().is_empty() => {
+                        format!("{}; {}", rule.declarations, style.trim())
+                    }
+                    _ => rule.declarations.clone(),
+                };
+                el.set_attribute("style", &style)?;
+                Ok(())
+            });
+            (Cow::Borrowed(&rule.selector), handler)
+        })
+        .collect();
+
+    let has_head = document.head || !document.html;
+    handlers.push(element!("head", |el| {
+        el.prepend(&head, ContentType::Html);
+        el.append(&style, ContentType::Html);
+        Ok(())
+    }));
+    handlers.push(element!("html", |el| {
+        if !has_head {
+            el.prepend(
+                &format!("<head>{}{}</head>", head, style),
+                ContentType::Html,
+            );
+        }
+        Ok(())
+    }));
+
+    let inlined = lol_html::rewrite_str(
+        &scanned,
+        RewriteStrSettings {
+            element_content_handlers: handlers,
+            ..RewriteStrSettings::new()
+        },
+    );
+    let html = match inlined {
+        Ok(inlined) if document.doctype => inlined,
+        Ok(inlined) => format!("<!DOCTYPE html>\n{}", inlined),
+        Err(e) => {
+            error!("Erreur d'intégration du CSS: {:?}", e);
+            warn(
+                &mut warnings,
+                "CSS non intégré aux éléments suite à une erreur".to_string(),
+            );
+            scanned
+        }
+    };
+    PreparedHtml { html, warnings }
+}
+
+/// Warns about declarations most mail clients ignore.
+fn check_declarations(declarations: &str, warnings: &mut Vec<String>) {
+    for declaration in declarations.split(';') {
+        let Some((property, value)) = declaration.split_once(':') else {
+            continue;
+        };
+        let property = property.trim().to_ascii_lowercase();
+        let value = value.trim().to_ascii_lowercase();
+        if property == "position" {
+            warn(
+                warnings,
+                "La propriété CSS position n'est pas supportée par la plupart des clients mail"
+                    .to_string(),
+            );
+        }
+        if property == "display" && (value.contains("flex") || value.contains("grid")) {
+            warn(
+                warnings,
+                format!(
+                    "display: {} n'est pas supporté par la plupart des clients mail",
+                    value
+                ),
+            );
+        }
+        if value.contains("var(") {
+            warn(
+                warnings,
+                "Les variables CSS ne sont pas supportées par la plupart des clients mail"
+                    .to_string(),
+            );
+        }
+    }
+}
+
+pub fn strip_comments(css: &str) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        output.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Approximate specificity of a selector: ids, classes, attributes and
+/// pseudo-classes, then element names.
+fn specificity(selector: &str) -> (u32, u32, u32) {
+    let mut specificity = (0, 0, 0);
+    for compound in selector
+        .split(|c: char| c.is_whitespace() || matches!(c, '>' | '+' | '~'))
+        .filter(|compound| !compound.is_empty())
+    {
+        if compound.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            specificity.2 += 1;
+        }
+        for c in compound.chars() {
+            match c {
+                '#' => specificity.0 += 1,
+                '.' | '[' | ':' => specificity.1 += 1,
+                _ => {}
+            }
+        }
+    }
+    specificity
+}
+
+fn parse_stylesheet(css: &str, warnings: &mut Vec<String>) -> Stylesheet {
+    let css = strip_comments(css);
+    let mut stylesheet = Stylesheet::default();
+    let mut rest = css.trim_start();
+    while !rest.is_empty() {
+        let open = rest.find('{');
+        // Statement at-rules, such as @import or @charset.
+        if rest.starts_with('@')
+            && let Some(end) = rest.find(';')
+            && open.is_none_or(|open| end < open)
+        {
+            if rest.starts_with("@import") {
+                warn(
+                    warnings,
+                    "@import n'est pas supporté par les clients mail".to_string(),
+                );
+            }
+            rest = rest[end + 1..].trim_start();
+            continue;
+        }
+        let Some(open) = open else {
+            break;
+        };
+        let mut depth = 0;
+        let mut close = None;
+        for (index, c) in rest[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(open + index);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let Some(close) = close else {
+            break;
+        };
+
+        let prelude = rest[..open].trim();
+        let block = &rest[open + 1..close];
+        if prelude.starts_with('@') {
+            stylesheet.retained.push_str(&rest[..=close]);
+            stylesheet.retained.push('\n');
+        } else {
+            check_declarations(block, warnings);
+            let declarations = block
+                .split(';')
+                .map(str::trim)
+                .filter(|declaration| !declaration.is_empty())
+                .collect::<Vec<_>>()
+                .join("; ");
+            for selector in prelude.split(',').map(str::trim) {
+                if selector.is_empty() || declarations.is_empty() {
+                    continue;
+                }
+                match selector.parse::<Selector>() {
+                    Ok(parsed) => stylesheet.rules.push(CssRule {
+                        selector: parsed,
+                        specificity: specificity(selector),
+                        declarations: declarations.clone(),
+                    }),
+                    Err(_) => {
+                        stylesheet
+                            .retained
+                            .push_str(&format!("{} {{ {} }}\n", selector, declarations));
+                        warn(
+                            warnings,
+                            format!(
+                                "Règle CSS {} conservée dans <style>, ignorée par certains clients mail",
+                                selector
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        rest = rest[close + 1..].trim_start();
+    }
+    stylesheet
+}
+
+/// Plain text of an HTML fragment, for the text alternative of a message:
+/// blocks on their own lines, list items as bullets, images by their `alt`
+/// text and links followed by their address.
+pub fn html_to_text(html: &str) -> String {
+    let link_text = Rc::new(RefCell::new(String::new()));
+    let marked = lol_html::rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("head, title, style, script", |el| {
+                    el.remove();
+                    Ok(())
+                }),
+                element!("br", |el| {
+                    el.replace(LINE_BREAK, ContentType::Text);
+                    Ok(())
+                }),
+                element!("div, tr", |el| {
+                    el.before(LINE_BREAK, ContentType::Text);
+                    el.after(LINE_BREAK, ContentType::Text);
+                    Ok(())
+                }),
+                element!("li", |el| {
+                    el.before(&format!("{}- ", LINE_BREAK), ContentType::Text);
+                    el.after(LINE_BREAK, ContentType::Text);
+                    Ok(())
+                }),
+                element!(TEXT_BLOCK_ELEMENTS, |el| {
+                    el.before(PARAGRAPH_BREAK, ContentType::Text);
+                    el.after(PARAGRAPH_BREAK, ContentType::Text);
+                    Ok(())
+                }),
+                element!("td, th", |el| {
+                    el.after(" ", ContentType::Text);
+                    Ok(())
+                }),
+                element!("img", |el| {
+                    let alt = el.get_attribute("alt").unwrap_or_default();
+                    el.replace(alt.trim(), ContentType::Text);
+                    Ok(())
+                }),
+                element!("a[href]", |el| {
+                    let href = el.get_attribute("href").unwrap_or_default();
+                    let href = decode_entities(href.trim());
+                    if href.is_empty() || href.starts_with('#') {
+                        return Ok(());
+                    }
+                    link_text.borrow_mut().clear();
+                    let link_text = link_text.clone();
+                    let handler: EndTagHandler = Box::new(move |end| {
+                        if decode_entities(link_text.borrow().trim()) != href {
+                            end.after(&format!(" ({})", href), ContentType::Text);
+                        }
+                        Ok(())
+                    });
+                    el.on_end_tag(handler)
+                }),
+                text!("a[href]", |chunk| {
+                    link_text.borrow_mut().push_str(chunk.as_str());
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+    let marked = match marked {
+        Ok(marked) => marked,
+        Err(e) => {
+            error!("Erreur de conversion du HTML en texte: {:?}", e);
+            html.to_string()
+        }
+    };
+
+    let raw = RefCell::new(String::new());
+    let collected = lol_html::rewrite_str(
+        &marked,
+        RewriteStrSettings {
+            document_content_handlers: vec![doc_text!(|chunk| {
+                raw.borrow_mut().push_str(chunk.as_str());
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    );
+    if let Err(e) = collected {
+        error!("Erreur de conversion du HTML en texte: {:?}", e);
+    }
+
+    // Whitespace collapses as in the rendered HTML, the breaks added above
+    // become new lines.
+    let mut text = String::new();
+    let mut space = false;
+    let mut lines = 0;
+    for c in decode_entities(&raw.into_inner()).chars() {
+        match c {
+            LINE_BREAK_CHAR => lines = lines.max(1),
+            PARAGRAPH_BREAK_CHAR => lines = 2,
+            c if c.is_whitespace() => space = true,
+            c => {
+                if !text.is_empty() {
+                    if lines > 0 {
+                        text.push_str(&"\n".repeat(lines));
+                    } else if space {
+                        text.push(' ');
+                    }
+                }
+                text.push(c);
+                space = false;
+                lines = 0;
+            }
+        }
+    }
+    text
+}
+
+/// Decodes the character references of HTML text, unknown ones are kept as
+/// is.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "eacute" => Some('é'),
+                "egrave" => Some('è'),
+                "ecirc" => Some('ê'),
+                "agrave" => Some('à'),
+                "ccedil" => Some('ç'),
+                "rsquo" => Some('’'),
+                "laquo" => Some('«'),
+                "raquo" => Some('»'),
+                "hellip" => Some('…'),
+                "ndash" => Some('–'),
+                "mdash" => Some('—'),
+                "euro" => Some('€'),
+                "copy" => Some('©'),
+                name if name.starts_with("#x") || name.starts_with("#X") => {
+                    u32::from_str_radix(&name[2..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                }
+                name if name.starts_with('#') => name[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            c.map(|c| (c, end))
+        });
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
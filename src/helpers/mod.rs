@@ -1,3 +1,18 @@
+pub mod anti_abuse;
+pub mod audit;
 pub mod auth;
+pub mod bounces;
+pub mod dkim;
 pub mod email;
+pub mod email_html;
+pub mod preflight;
+pub mod render;
 pub mod response;
+pub mod sanitize;
+pub mod sender;
+pub mod signing;
+pub mod spam;
+pub mod suppressions;
+pub mod throttle;
+pub mod tracking;
+pub mod webhooks;
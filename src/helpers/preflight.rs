@@ -0,0 +1,203 @@
+use std::cell::RefCell;
+
+use chrono::Utc;
+use lol_html::{RewriteStrSettings, element, text};
+use sqlx::SqlitePool;
+use tracing::error;
+
+use crate::helpers::render;
+use crate::helpers::spam::SpamRules;
+use crate::models::newsletters::{NewsletterForSend, Preflight};
+
+/// Size past which Gmail clips a message behind a "view entire message" link.
+const GMAIL_CLIP_BYTES: usize = 102 * 1024;
+/// Words of the address or text of a link to the preferences page, besides
+/// the `unsubscribe_url` merge tag.
+const UNSUBSCRIBE_HINTS: [&str; 5] = [
+    "unsubscribe",
+    "désinscri",
+    "desinscri",
+    "désabonne",
+    "preferences",
+];
+
+#[derive(Default)]
+struct HtmlFindings {
+    unsubscribe_link: bool,
+    images_without_alt: usize,
+    http_links: usize,
+}
+
+fn is_unsubscribe(content: &str) -> bool {
+    let content = content.to_lowercase();
+    content.contains("unsubscribe_url")
+        || UNSUBSCRIBE_HINTS.iter().any(|hint| content.contains(hint))
+}
+
+fn is_http(url: &str) -> bool {
+    url.trim().to_ascii_lowercase().starts_with("http://")
+}
+
+fn scan_html(html: &str) -> HtmlFindings {
+    let findings = RefCell::new(HtmlFindings::default());
+    let link_text = RefCell::new(String::new());
+    let scanned = lol_html::rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("a[href]", |el| {
+                    let href = el.get_attribute("href").unwrap_or_default();
+                    let mut findings = findings.borrow_mut();
+                    findings.unsubscribe_link |= is_unsubscribe(&href);
+                    if is_http(&href) {
+                        findings.http_links += 1;
+                    }
+                    Ok(())
+                }),
+                text!("a", |chunk| {
+                    let mut link_text = link_text.borrow_mut();
+                    link_text.push_str(chunk.as_str());
+                    if chunk.last_in_text_node() {
+                        findings.borrow_mut().unsubscribe_link |= is_unsubscribe(&link_text);
+                        link_text.clear();
+                    }
+                    Ok(())
+                }),
+                element!("img", |el| {
+                    let mut findings = findings.borrow_mut();
+                    if !el.has_attribute("alt") {
+                        findings.images_without_alt += 1;
+                    }
+                    if el.get_attribute("src").is_some_and(|src| is_http(&src)) {
+                        findings.http_links += 1;
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+    if let Err(e) = scanned {
+        error!("Erreur d'analyse du HTML: {:?}", e);
+    }
+    findings.into_inner()
+}
+
+/// Checks a newsletter before it is sent. Errors block the dispatch,
+/// warnings are for the editor to judge.
+pub async fn check(
+    pool: &SqlitePool,
+    newsletter: &NewsletterForSend,
+) -> Result<Preflight, sqlx::Error> {
+    let mut preflight = Preflight::default();
+    if newsletter.subject.trim().is_empty() {
+        preflight
+            .errors
+            .push("La newsletter n'a pas d'objet".to_string());
+    }
+
+    let rendered = render::render_body(newsletter);
+    let mut unknown = render::unknown_tags(&newsletter.subject);
+    for content in [Some(&rendered.body), rendered.text.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        for tag in render::unknown_tags(content) {
+            if !unknown.contains(&tag) {
+                unknown.push(tag);
+            }
+        }
+    }
+    if !unknown.is_empty() {
+        preflight.errors.push(format!(
+            "Balises de fusion inconnues, laissées telles quelles: {}",
+            unknown
+                .iter()
+                .map(|tag| format!("{{{{ {} }}}}", tag))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    let findings = if newsletter.content_html.is_some() {
+        scan_html(&rendered.body)
+    } else {
+        HtmlFindings {
+            unsubscribe_link: is_unsubscribe(&rendered.body),
+            http_links: rendered.body.matches("http://").count(),
+            ..Default::default()
+        }
+    };
+    if !findings.unsubscribe_link {
+        preflight.errors.push(
+            "Aucun lien de désinscription, ajoutez {{ unsubscribe_url }} au contenu ou au thème"
+                .to_string(),
+        );
+    }
+    if newsletter.content_html.is_some() && rendered.body.len() > GMAIL_CLIP_BYTES {
+        preflight.warnings.push(format!(
+            "Message de {} Ko, Gmail le tronque au-delà de 102 Ko",
+            rendered.body.len() / 1024
+        ));
+    }
+    if findings.images_without_alt > 0 {
+        preflight.warnings.push(format!(
+            "{} image(s) sans texte alternatif (alt)",
+            findings.images_without_alt
+        ));
+    }
+    if findings.http_links > 0 {
+        preflight.warnings.push(format!(
+            "{} lien(s) ou image(s) en http au lieu de https",
+            findings.http_links
+        ));
+    }
+    let spam = SpamRules::get().score(newsletter, &rendered);
+    if spam.spammy {
+        preflight.warnings.push(format!(
+            "Contenu susceptible d'être classé en spam (score {}, seuil {})",
+            spam.score, spam.threshold
+        ));
+    }
+    preflight.warnings.extend(rendered.warnings);
+
+    let (recipients, suppressed, without_token): (i64, i64, i64) = sqlx::query_as(
+        r#"
+        select count(*), coalesce(sum(s.email is not null), 0),
+            coalesce(sum(r.unsubscribe_token is null or r.unsubscribe_token = ''), 0)
+        from (
+            select distinct c.id, lower(c.email) as email, c.unsubscribe_token
+            from contacts c
+            join contact_list_members clm on c.id = clm.contact_id
+            join sending_contact_lists scl on clm.list_id = scl.contact_list_id
+            where scl.sending_id = ?
+              and (c.paused_until is null or c.paused_until <= ?)
+        ) r
+        left join suppressions s on s.email = r.email
+        "#,
+    )
+    .bind(&newsletter.id)
+    .bind(Utc::now())
+    .fetch_one(pool)
+    .await?;
+    if recipients == 0 {
+        preflight.errors.push("Aucun destinataire".to_string());
+    } else if suppressed == recipients {
+        preflight
+            .errors
+            .push("Tous les destinataires sont dans la liste de suppression".to_string());
+    } else if suppressed > 0 {
+        preflight.warnings.push(format!(
+            "{} destinataire(s) de la liste de suppression ne recevront pas la newsletter",
+            suppressed
+        ));
+    }
+    if without_token > 0 {
+        preflight.errors.push(format!(
+            "{} destinataire(s) sans jeton de désinscription, leur lien serait inutilisable",
+            without_token
+        ));
+    }
+
+    Ok(preflight)
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use serde_json::Value;
+
+use crate::helpers::email_html;
+use crate::models::newsletters::NewsletterForSend;
+
+/// Contact fields available as merge tags, besides `custom.<key>`.
+const CONTACT_TAGS: [&str; 7] = [
+    "email",
+    "first_name",
+    "last_name",
+    "address",
+    "postal_code",
+    "city",
+    "unsubscribe_url",
+];
+
+/// Values of the merge tags for one recipient.
+#[derive(Debug, Default)]
+pub struct MergeFields {
+    values: HashMap<String, String>,
+}
+
+impl MergeFields {
+    /// `custom_fields` is the JSON object stored on the contact.
+    pub fn new(email: &str, custom_fields: Option<&str>) -> Self {
+        let mut values = HashMap::from([("email".to_string(), email.to_string())]);
+        let custom = custom_fields.and_then(|raw| serde_json::from_str::<Value>(raw).ok());
+        if let Some(Value::Object(custom)) = custom {
+            for (key, value) in custom {
+                let value = match value {
+                    Value::Null => continue,
+                    Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                values.insert(format!("custom.{}", key), value);
+            }
+        }
+        Self { values }
+    }
+
+    pub fn with(mut self, name: &str, value: Option<&str>) -> Self {
+        if let Some(value) = value {
+            self.values.insert(name.to_string(), value.to_string());
+        }
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .get(name)
+            .map(String::as_str)
+            .filter(|value| !value.trim().is_empty())
+    }
+}
+
+fn is_known_tag(name: &str) -> bool {
+    CONTACT_TAGS.contains(&name)
+        || name
+            .strip_prefix("custom.")
+            .is_some_and(|key| !key.is_empty())
+}
+
+/// Merge tags of `template`, as the name and fallback of each tag with the
+/// part of the template it spans.
+fn find_tags(template: &str) -> Vec<(&str, &str, std::ops::Range<usize>)> {
+    let mut tags = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = template[offset..].find("{{").map(|start| offset + start) {
+        let Some(length) = template[start + 2..].find("}}") else {
+            break;
+        };
+        let tag = &template[start + 2..start + 2 + length];
+        let (name, fallback) = match tag.split_once('|') {
+            Some((name, fallback)) => (name.trim(), fallback.trim()),
+            None => (tag.trim(), ""),
+        };
+        offset = start + 4 + length;
+        tags.push((name, fallback, start..offset));
+    }
+    tags
+}
+
+fn merge(template: &str, fields: &MergeFields, html: bool) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut copied = 0;
+    for (name, fallback, range) in find_tags(template) {
+        if !is_known_tag(name) {
+            continue;
+        }
+        output.push_str(&template[copied..range.start]);
+        let value = fields.get(name).unwrap_or(fallback);
+        if html {
+            output.push_str(&escape_html(value));
+        } else {
+            // Values end up in headers, keep them on one line.
+            output.push_str(&value.replace(['\r', '\n'], " "));
+        }
+        copied = range.end;
+    }
+    output.push_str(&template[copied..]);
+    output
+}
+
+/// Replaces `{{ name }}` and `{{ name | fallback }}` tags with the values of
+/// `fields`. Tags whose value is missing get their fallback, or nothing;
+/// unknown tags are left as written.
+pub fn merge_tags(template: &str, fields: &MergeFields) -> String {
+    merge(template, fields, false)
+}
+
+/// `merge_tags` for an HTML template, values escaped.
+pub fn merge_tags_html(template: &str, fields: &MergeFields) -> String {
+    merge(template, fields, true)
+}
+
+/// Names of the tags of `template` that `merge_tags` leaves as written.
+pub fn unknown_tags(template: &str) -> Vec<String> {
+    let mut unknown: Vec<String> = Vec::new();
+    for (name, _, _) in find_tags(template) {
+        if !is_known_tag(name) && !unknown.iter().any(|tag| tag == name) {
+            unknown.push(name.to_string());
+        }
+    }
+    unknown
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Body of a newsletter as sent, before per-recipient tracking.
+pub struct RenderedBody {
+    /// Sent as HTML, the plain content as is when the newsletter has no HTML.
+    pub body: String,
+    /// Plain text alternative of an HTML body.
+    pub text: Option<String>,
+    /// Constructs mail clients may not display as intended.
+    pub warnings: Vec<String>,
+}
+
+/// Wraps the HTML content in its theme, inlines its CSS and adds the
+/// preheader.
+pub fn render_body(newsletter: &NewsletterForSend) -> RenderedBody {
+    let Some(html) = &newsletter.content_html else {
+        return RenderedBody {
+            body: newsletter.content_plain.clone().unwrap_or_default(),
+            text: None,
+            warnings: Vec::new(),
+        };
+    };
+    let themed = format!(
+        "{}{}{}",
+        newsletter.theme_header.as_deref().unwrap_or_default(),
+        html,
+        newsletter.theme_footer.as_deref().unwrap_or_default()
+    );
+    let prepared = email_html::prepare(&themed, newsletter.theme_stylesheet.as_deref());
+    let body = match &newsletter.preheader {
+        Some(preheader) => inject_preheader(&prepared.html, preheader),
+        None => prepared.html,
+    };
+    // The theme's header and footer are HTML only, their text is added
+    // around the plain content.
+    let text = newsletter.content_plain.as_deref().map(|plain| {
+        [
+            newsletter
+                .theme_header
+                .as_deref()
+                .map(email_html::html_to_text),
+            Some(plain.trim().to_string()),
+            newsletter
+                .theme_footer
+                .as_deref()
+                .map(email_html::html_to_text),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+            + "\n"
+    });
+    RenderedBody {
+        body,
+        text,
+        warnings: prepared.warnings,
+    }
+}
+
+/// Adds `preheader` as hidden text at the start of the body, where mail
+/// clients pick the preview shown next to the subject.
+pub fn inject_preheader(html: &str, preheader: &str) -> String {
+    let hidden = format!(
+        "<div style=\"display:none;font-size:1px;line-height:1px;max-height:0;max-width:0;opacity:0;overflow:hidden;mso-hide:all;\">{}</div>",
+        escape_html(preheader.trim())
+    );
+    let body_start = html
+        .to_ascii_lowercase()
+        .find("<body")
+        .and_then(|start| html[start..].find('>').map(|end| start + end + 1));
+    match body_start {
+        Some(index) => format!("{}{}{}", &html[..index], hidden, &html[index..]),
+        None => format!("{}{}", hidden, html),
+    }
+}
+
+fn markdown_options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH
+}
+
+/// Renders Markdown to HTML. Raw HTML in the source is escaped rather than
+/// passed through.
+pub fn markdown_to_html(source: &str) -> String {
+    let events = Parser::new_ext(source, markdown_options()).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        event => event,
+    });
+    let mut html = String::with_capacity(source.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+/// Renders Markdown to the plain text alternative of a message, links
+/// followed by their address.
+pub fn markdown_to_text(source: &str) -> String {
+    let mut text = String::with_capacity(source.len());
+    // Next number of each open list, `None` for bullet lists.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    // Address of each open link and where its text starts, `None` for images.
+    let mut links: Vec<Option<(String, usize)>> = Vec::new();
+    let end_line = |text: &mut String| {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+    };
+
+    for event in Parser::new_ext(source, markdown_options()) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                end_line(&mut text);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::End(TagEnd::Item) | Event::End(TagEnd::TableHead | TagEnd::TableRow) => {
+                end_line(&mut text)
+            }
+            Event::End(TagEnd::TableCell) => text.push('\t'),
+            Event::End(TagEnd::Paragraph) => {
+                text.push_str(if lists.is_empty() { "\n\n" } else { "\n" })
+            }
+            Event::End(TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::Table) => {
+                end_line(&mut text);
+                text.push('\n');
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                links.push(Some((dest_url.to_string(), text.len())))
+            }
+            Event::Start(Tag::Image { .. }) => links.push(None),
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                if let Some(Some((url, start))) = links.pop()
+                    && text[start..] != url
+                {
+                    text.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::Text(content)
+            | Event::Code(content)
+            | Event::Html(content)
+            | Event::InlineHtml(content) => text.push_str(&content),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("----------\n\n"),
+            _ => {}
+        }
+    }
+    format!("{}\n", text.trim_end())
+}
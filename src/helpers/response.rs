@@ -4,6 +4,7 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
+use validator::ValidationErrors;
 
 #[derive(Serialize)]
 pub struct ApiResponse<T> {
@@ -37,3 +38,18 @@ pub fn response_success<T: Serialize>(status: StatusCode, data: T) -> Response {
 pub fn response_err(status: StatusCode, msg: String) -> Response {
     (status, Json(ApiResponse::<()>::error(msg))).into_response()
 }
+
+pub fn extract_errors(errors: ValidationErrors) -> String {
+    errors
+        .field_errors()
+        .iter()
+        .map(|(field, errs)| {
+            let messages: Vec<String> = errs
+                .iter()
+                .filter_map(|e| e.message.clone().map(|msg| msg.into_owned()))
+                .collect();
+            format!("{}: {}", field, messages.join(", "))
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
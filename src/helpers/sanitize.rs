@@ -0,0 +1,266 @@
+use std::cell::RefCell;
+
+use lol_html::errors::RewritingError;
+use lol_html::html_content::ContentType;
+use lol_html::{RewriteStrSettings, element, text};
+
+use crate::helpers::email_html::strip_comments;
+
+/// Elements kept, with the attributes they may carry besides the global ones.
+const ALLOWED_ELEMENTS: [(&str, &[&str]); 60] = [
+    ("html", &["xmlns"]),
+    ("head", &[]),
+    ("body", &["bgcolor"]),
+    ("meta", &["charset", "name", "content", "http-equiv"]),
+    ("title", &[]),
+    ("style", &["type", "media"]),
+    ("a", &["href", "target", "name", "rel"]),
+    ("img", &["src", "alt", "width", "height", "border", "align"]),
+    ("picture", &[]),
+    ("source", &["srcset", "media", "type"]),
+    (
+        "table",
+        &[
+            "width",
+            "height",
+            "border",
+            "cellpadding",
+            "cellspacing",
+            "bgcolor",
+            "align",
+            "background",
+        ],
+    ),
+    ("caption", &[]),
+    ("colgroup", &["span", "width"]),
+    ("col", &["span", "width"]),
+    ("thead", &[]),
+    ("tbody", &[]),
+    ("tfoot", &[]),
+    ("tr", &["align", "valign", "bgcolor", "height"]),
+    (
+        "td",
+        &[
+            "width",
+            "height",
+            "align",
+            "valign",
+            "bgcolor",
+            "colspan",
+            "rowspan",
+            "background",
+        ],
+    ),
+    (
+        "th",
+        &[
+            "width", "height", "align", "valign", "bgcolor", "colspan", "rowspan", "scope",
+        ],
+    ),
+    ("div", &["align"]),
+    ("span", &[]),
+    ("p", &["align"]),
+    ("br", &[]),
+    ("hr", &["width", "size", "align", "noshade"]),
+    ("h1", &["align"]),
+    ("h2", &["align"]),
+    ("h3", &["align"]),
+    ("h4", &["align"]),
+    ("h5", &["align"]),
+    ("h6", &["align"]),
+    ("center", &[]),
+    ("font", &["color", "face", "size"]),
+    ("strong", &[]),
+    ("b", &[]),
+    ("em", &[]),
+    ("i", &[]),
+    ("u", &[]),
+    ("s", &[]),
+    ("strike", &[]),
+    ("small", &[]),
+    ("sub", &[]),
+    ("sup", &[]),
+    ("abbr", &[]),
+    ("code", &[]),
+    ("pre", &[]),
+    ("blockquote", &[]),
+    ("address", &[]),
+    ("ul", &["type"]),
+    ("ol", &["type", "start"]),
+    ("li", &[]),
+    ("dl", &[]),
+    ("dt", &[]),
+    ("dd", &[]),
+    ("header", &[]),
+    ("footer", &[]),
+    ("section", &[]),
+    ("article", &[]),
+    ("main", &[]),
+    ("nav", &[]),
+];
+/// Attributes allowed on every kept element, besides `aria-*` and `data-*`.
+const GLOBAL_ATTRIBUTES: [&str; 7] = ["style", "class", "id", "dir", "lang", "title", "role"];
+/// Elements removed along with their content, the others not allowed only
+/// lose their tags.
+const DROPPED_WITH_CONTENT: [&str; 18] = [
+    "script", "noscript", "template", "iframe", "frame", "frameset", "object", "embed", "applet",
+    "form", "input", "select", "textarea", "button", "svg", "math", "base", "link",
+];
+const URL_ATTRIBUTES: [&str; 4] = ["href", "src", "background", "srcset"];
+const URL_SCHEMES: [&str; 5] = ["http", "https", "mailto", "tel", "cid"];
+/// CSS running script in the clients that still support it.
+const UNSAFE_CSS: [&str; 4] = ["expression(", "javascript:", "behavior:", "-moz-binding"];
+
+/// HTML kept by the allowlist, with what was taken out of it.
+pub struct SanitizedHtml {
+    pub html: String,
+    /// What was removed, e.g. `élément <script>` or `attribut onclick de <a>`,
+    /// with the number of occurrences when more than one.
+    pub removed: Vec<String>,
+}
+
+/// Whether a link or image address uses a scheme mail clients handle
+/// safely; relative addresses and anchors have none.
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match url.find(':') {
+        Some(index) if !url[..index].contains(['/', '?', '#']) => {
+            URL_SCHEMES.contains(&&url[..index])
+        }
+        _ => true,
+    }
+}
+
+fn is_allowed_attribute(tag_attributes: &[&str], name: &str, value: &str) -> bool {
+    let allowed = GLOBAL_ATTRIBUTES.contains(&name)
+        || tag_attributes.contains(&name)
+        || name.starts_with("aria-")
+        || name.starts_with("data-");
+    if !allowed {
+        return false;
+    }
+    if URL_ATTRIBUTES.contains(&name) {
+        // srcset lists addresses followed by their width or density.
+        return value
+            .split(',')
+            .all(|candidate| is_safe_url(candidate.split_whitespace().next().unwrap_or("")));
+    }
+    if name == "style" {
+        return is_safe_css(value);
+    }
+    true
+}
+
+/// Whether CSS is free of script, once whitespace and escapes that could
+/// split a pattern are taken out.
+fn is_safe_css(css: &str) -> bool {
+    let compact: String = strip_comments(css)
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\\')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    !UNSAFE_CSS.iter().any(|pattern| compact.contains(pattern))
+}
+
+/// Stylesheet without its declarations running script, with the number of
+/// declarations removed.
+pub fn sanitize_stylesheet(css: &str) -> (String, usize) {
+    let mut sanitized = String::with_capacity(css.len());
+    let mut removed = 0;
+    for part in strip_comments(css).split_inclusive([';', '{', '}']) {
+        if part.ends_with('{') || is_safe_css(part) {
+            sanitized.push_str(part);
+            continue;
+        }
+        removed += 1;
+        if part.ends_with('}') {
+            sanitized.push('}');
+        }
+    }
+    (sanitized, removed)
+}
+
+/// Keeps the elements and attributes of an email-oriented allowlist, and
+/// links or images with safe addresses only.
+pub fn sanitize_html(html: &str) -> Result<SanitizedHtml, RewritingError> {
+    let removed: RefCell<Vec<(String, usize)>> = RefCell::new(Vec::new());
+    let record = |item: String| {
+        let mut removed = removed.borrow_mut();
+        match removed.iter_mut().find(|(existing, _)| *existing == item) {
+            Some((_, count)) => *count += 1,
+            None => removed.push((item, 1)),
+        }
+    };
+
+    let style = RefCell::new(String::new());
+
+    let html = lol_html::rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                text!("style", |chunk| {
+                    style.borrow_mut().push_str(chunk.as_str());
+                    if !chunk.last_in_text_node() {
+                        chunk.remove();
+                        return Ok(());
+                    }
+                    let (css, dropped) = sanitize_stylesheet(&style.take());
+                    for _ in 0..dropped {
+                        record("déclaration CSS de <style>".to_string());
+                    }
+                    chunk.replace(&css, ContentType::Html);
+                    Ok(())
+                }),
+                element!("*", |el| {
+                    let tag = el.tag_name();
+                    if DROPPED_WITH_CONTENT.contains(&tag.as_str()) {
+                        el.remove();
+                        record(format!("élément <{}>", tag));
+                        return Ok(());
+                    }
+                    let Some((_, tag_attributes)) =
+                        ALLOWED_ELEMENTS.iter().find(|(allowed, _)| *allowed == tag)
+                    else {
+                        el.remove_and_keep_content();
+                        record(format!("élément <{}>", tag));
+                        return Ok(());
+                    };
+                    let rejected: Vec<String> = el
+                        .attributes()
+                        .iter()
+                        .filter(|attribute| {
+                            !is_allowed_attribute(
+                                tag_attributes,
+                                &attribute.name(),
+                                &attribute.value(),
+                            )
+                        })
+                        .map(|attribute| attribute.name())
+                        .collect();
+                    for name in rejected {
+                        el.remove_attribute(&name);
+                        record(format!("attribut {} de <{}>", name, tag));
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    Ok(SanitizedHtml {
+        html,
+        removed: removed
+            .into_inner()
+            .into_iter()
+            .map(|(item, count)| match count {
+                1 => item,
+                count => format!("{} (x{})", item, count),
+            })
+            .collect(),
+    })
+}
@@ -0,0 +1,677 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::APP_CONFIG;
+use crate::config::config::EmailRetryConfig;
+use crate::helpers::bounces::{BounceKind, BounceReport, record_bounce};
+use crate::helpers::email::{Email, SendFailure, Sender};
+use crate::helpers::render::{self, MergeFields, RenderedBody};
+use crate::helpers::suppressions::is_suppressed;
+use crate::helpers::throttle::Throttle;
+use crate::helpers::tracking::inject_tracking;
+use crate::models::newsletters::{NewsletterForSend, QueuedDelivery, SendProgress};
+
+/// Deliveries loaded from the queue at a time.
+const BATCH_SIZE: i64 = 100;
+
+/// What a running dispatch has been asked to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+struct Dispatch {
+    progress: watch::Receiver<SendProgress>,
+    control: watch::Sender<Control>,
+}
+
+/// Dispatches currently running, by newsletter id.
+static DISPATCHES: LazyLock<Mutex<HashMap<String, Dispatch>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Live progress of a running dispatch.
+pub fn subscribe(newsletter_id: &str) -> Option<watch::Receiver<SendProgress>> {
+    DISPATCHES
+        .lock()
+        .expect("Dispatch registry poisoned")
+        .get(newsletter_id)
+        .map(|dispatch| dispatch.progress.clone())
+}
+
+/// Sends `control` to the running dispatch of a newsletter. Returns `false`
+/// when nothing is running or the dispatch is not in a state `control`
+/// applies to (only a running dispatch can be paused, only a paused one
+/// resumed, and a cancelled one takes no further orders).
+pub fn send_control(newsletter_id: &str, control: Control) -> bool {
+    let dispatches = DISPATCHES.lock().expect("Dispatch registry poisoned");
+    let Some(dispatch) = dispatches.get(newsletter_id) else {
+        return false;
+    };
+    dispatch.control.send_if_modified(|current| {
+        let allowed = match control {
+            Control::Run => *current == Control::Pause,
+            Control::Pause => *current == Control::Run,
+            Control::Cancel => *current != Control::Cancel,
+        };
+        if allowed {
+            *current = control;
+        }
+        allowed
+    })
+}
+
+pub async fn set_status(
+    pool: &SqlitePool,
+    newsletter_id: &str,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("update sendings set status = ?, updated_at = ? where id = ?")
+        .bind(status)
+        .bind(Utc::now())
+        .bind(newsletter_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Marks the deliveries still queued as cancelled, and the newsletter with them.
+pub async fn cancel_queued(pool: &SqlitePool, newsletter_id: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "update deliveries set status = 'cancelled', updated_at = ? where sending_id = ? and status = 'queued'",
+    )
+    .bind(Utc::now())
+    .bind(newsletter_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("update sendings set status = 'cancelled', updated_at = ? where id = ?")
+        .bind(Utc::now())
+        .bind(newsletter_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+/// Counters of a newsletter's deliveries as stored in the database.
+pub async fn load_progress(
+    pool: &SqlitePool,
+    newsletter_id: &str,
+) -> Result<SendProgress, sqlx::Error> {
+    let (status,): (String,) = sqlx::query_as("select status from sendings where id = ?")
+        .bind(newsletter_id)
+        .fetch_one(pool)
+        .await?;
+    let (total, queued, deferred, sent, failed, suppressed, cancelled): (
+        i64,
+        i64,
+        i64,
+        i64,
+        i64,
+        i64,
+        i64,
+    ) = sqlx::query_as(
+        // A bounced delivery without message id was refused by the relay,
+        // it never went out.
+        r#"
+        select count(*),
+            coalesce(sum(status = 'queued'), 0),
+            coalesce(sum(status = 'queued' and next_attempt_at > ?), 0),
+            coalesce(sum(status = 'sent' or (status = 'bounced' and message_id is not null)), 0),
+            coalesce(sum(status = 'failed' or (status = 'bounced' and message_id is null)), 0),
+            coalesce(sum(status = 'suppressed'), 0),
+            coalesce(sum(status = 'cancelled'), 0)
+        from deliveries
+        where sending_id = ?
+        "#,
+    )
+    .bind(Utc::now())
+    .bind(newsletter_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(SendProgress {
+        newsletter_id: newsletter_id.to_string(),
+        status,
+        total,
+        queued,
+        deferred,
+        sent,
+        failed,
+        suppressed,
+        cancelled,
+        ..Default::default()
+    })
+}
+
+/// Starts sending the queued deliveries of `newsletter` in the background.
+/// Returns `false` when a dispatch is already running for it.
+pub fn start(pool: SqlitePool, newsletter: NewsletterForSend, progress: SendProgress) -> bool {
+    let (progress_tx, progress_rx) = watch::channel(SendProgress {
+        status: "sending".to_string(),
+        running: true,
+        ..progress
+    });
+    let (control_tx, control_rx) = watch::channel(Control::Run);
+    {
+        let mut dispatches = DISPATCHES.lock().expect("Dispatch registry poisoned");
+        if dispatches.contains_key(&newsletter.id) {
+            return false;
+        }
+        dispatches.insert(
+            newsletter.id.clone(),
+            Dispatch {
+                progress: progress_rx,
+                control: control_tx,
+            },
+        );
+    }
+
+    tokio::spawn(async move {
+        let newsletter_id = newsletter.id.clone();
+        if let Err(e) = run(&pool, &newsletter, &progress_tx, control_rx).await {
+            error!("Erreur d'envoi de la newsletter {}: {:?}", newsletter_id, e);
+        }
+        progress_tx.send_modify(|progress| progress.running = false);
+        DISPATCHES
+            .lock()
+            .expect("Dispatch registry poisoned")
+            .remove(&newsletter_id);
+    });
+    true
+}
+
+/// Page where a contact manages their subscriptions, the `unsubscribe_url`
+/// merge tag. Recipients that are not contacts (seed addresses, test sends)
+/// have no subscription and get the site's address, so that their link still
+/// leads somewhere.
+fn unsubscribe_url(token: Option<&str>) -> String {
+    let site_url = APP_CONFIG
+        .get()
+        .expect("Configuration not initialized")
+        .site
+        .site_url
+        .trim_end_matches('/');
+    match token {
+        Some(token) => format!("{}/preferences/{}", site_url, token),
+        None => site_url.to_string(),
+    }
+}
+
+/// Message of one delivery.
+pub struct Personalized {
+    pub subject: String,
+    pub body: String,
+    pub text: Option<String>,
+}
+
+/// Fills in the merge tags of the subject and content, and adds open/click
+/// tracking to the HTML body unless the contact opted out.
+pub fn personalize(
+    newsletter: &NewsletterForSend,
+    delivery: &QueuedDelivery,
+    rendered: &RenderedBody,
+) -> Personalized {
+    let unsubscribe_url = unsubscribe_url(delivery.unsubscribe_token.as_deref());
+    let fields = MergeFields::new(&delivery.email, delivery.custom_fields.as_deref())
+        .with("first_name", delivery.first_name.as_deref())
+        .with("last_name", delivery.last_name.as_deref())
+        .with("address", delivery.address.as_deref())
+        .with("postal_code", delivery.postal_code.as_deref())
+        .with("city", delivery.city.as_deref())
+        .with("unsubscribe_url", Some(&unsubscribe_url));
+    let subject = render::merge_tags(&newsletter.subject, &fields);
+    let text = rendered
+        .text
+        .as_deref()
+        .map(|text| render::merge_tags(text, &fields));
+    if newsletter.content_html.is_none() {
+        return Personalized {
+            subject,
+            body: render::merge_tags(&rendered.body, &fields),
+            text,
+        };
+    }
+
+    let body = render::merge_tags_html(&rendered.body, &fields);
+    let body = if !delivery.tracking_disabled && (newsletter.track_opens || newsletter.track_clicks)
+    {
+        inject_tracking(
+            &body,
+            &delivery.id,
+            newsletter.track_opens,
+            newsletter.track_clicks,
+        )
+    } else {
+        body
+    };
+    Personalized {
+        subject,
+        body,
+        text,
+    }
+}
+
+/// What became of one delivery.
+enum Outcome {
+    Sent {
+        message_id: String,
+        relay: &'static str,
+    },
+    Suppressed,
+    /// Temporary failure, the delivery is tried again at `retry_at`.
+    Retry {
+        failure: SendFailure,
+        retry_at: DateTime<Utc>,
+    },
+    /// The relay refused the recipient: a bounce.
+    Rejected(SendFailure),
+    /// Permanent failure that is not about the recipient (authentication,
+    /// sender refused...): it would fail for everyone, the dispatch pauses
+    /// and the delivery stays queued.
+    Halted(SendFailure),
+    Failed(SendFailure),
+}
+
+fn retry_delay(config: &EmailRetryConfig, attempts: u32) -> chrono::Duration {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    let secs = config
+        .retry_base_secs
+        .saturating_mul(factor)
+        .min(config.max_retry_delay_secs);
+    chrono::Duration::seconds(secs as i64)
+}
+
+/// Sends one delivery.
+async fn deliver(
+    pool: &SqlitePool,
+    newsletter: &NewsletterForSend,
+    sender: &Sender,
+    delivery: &QueuedDelivery,
+    rendered: &RenderedBody,
+) -> Outcome {
+    match is_suppressed(pool, &delivery.email).await {
+        Ok(true) => {
+            info!("Adresse supprimée, envoi ignoré: {}", delivery.email);
+            return Outcome::Suppressed;
+        }
+        Ok(false) => {}
+        Err(e) => {
+            error!(
+                "Erreur de vérification de la liste de suppression pour {}: {:?}",
+                delivery.email, e
+            );
+            return Outcome::Failed(SendFailure::transient(e.to_string()));
+        }
+    }
+
+    let email_helper = Email::get();
+    let sender = sender.clone();
+    let delivery_id = delivery.id.clone();
+    let to = delivery.email.clone();
+    let Personalized {
+        subject,
+        body,
+        text,
+    } = personalize(newsletter, delivery, rendered);
+    // lettre's SMTP transport is blocking.
+    let result = tokio::task::spawn_blocking(move || {
+        email_helper.send_email(&sender, &delivery_id, &to, &subject, &body, text.as_deref())
+    })
+    .await;
+
+    let failure = match result {
+        Ok(Ok(relay)) => {
+            info!("Email envoyé à {} via {}", delivery.email, relay);
+            return Outcome::Sent {
+                message_id: email_helper.message_id(&delivery.id),
+                relay,
+            };
+        }
+        Ok(Err(failure)) => failure,
+        Err(e) => {
+            error!("Tâche d'envoi interrompue pour {}: {:?}", delivery.email, e);
+            SendFailure::transient(e.to_string())
+        }
+    };
+
+    let retry = &APP_CONFIG
+        .get()
+        .expect("Configuration not initialized")
+        .email
+        .retry;
+    let attempts = delivery.attempts + 1;
+    if failure.recipient_rejected() {
+        return Outcome::Rejected(failure);
+    }
+    // A message that cannot be built only fails for its recipient.
+    if failure.message_invalid() {
+        return Outcome::Failed(failure);
+    }
+    if failure.permanent {
+        return Outcome::Halted(failure);
+    }
+    if attempts >= retry.max_attempts {
+        return Outcome::Failed(failure);
+    }
+    let retry_at = Utc::now() + retry_delay(retry, attempts);
+    info!(
+        "Échec temporaire de l'envoi à {} (tentative {}), nouvel essai le {}",
+        delivery.email, attempts, retry_at
+    );
+    Outcome::Retry { failure, retry_at }
+}
+
+/// Records a recipient refused by the relay as a bounce, hard unless the
+/// mailbox is only full, which suppresses the address.
+async fn record_rejection(
+    pool: &SqlitePool,
+    delivery: &QueuedDelivery,
+    failure: &SendFailure,
+) -> Result<(), sqlx::Error> {
+    let config = &APP_CONFIG
+        .get()
+        .expect("Configuration not initialized")
+        .bounces;
+    let enhanced_code = failure.enhanced_code();
+    let report = BounceReport {
+        recipient: delivery.email.to_lowercase(),
+        kind: if enhanced_code == Some("5.2.2") {
+            BounceKind::Soft
+        } else {
+            BounceKind::Hard
+        },
+        status_code: enhanced_code
+            .map(str::to_string)
+            .or_else(|| failure.smtp_code.clone()),
+        diagnostic: Some(failure.message.clone()),
+    };
+    record_bounce(pool, config, Some(&delivery.id), None, &report).await?;
+    Ok(())
+}
+
+/// Blocks while the dispatch is paused. Returns `false` once it is cancelled.
+async fn wait_if_paused(
+    pool: &SqlitePool,
+    newsletter_id: &str,
+    control_rx: &mut watch::Receiver<Control>,
+    progress_tx: &watch::Sender<SendProgress>,
+) -> Result<bool, sqlx::Error> {
+    let mut paused = false;
+    loop {
+        let control = *control_rx.borrow_and_update();
+        match control {
+            Control::Cancel => return Ok(false),
+            Control::Run => {
+                if paused {
+                    set_status(pool, newsletter_id, "sending").await?;
+                    progress_tx.send_modify(|progress| progress.status = "sending".to_string());
+                    info!("Envoi de la newsletter {} repris", newsletter_id);
+                }
+                return Ok(true);
+            }
+            Control::Pause => {
+                if !paused {
+                    paused = true;
+                    set_status(pool, newsletter_id, "paused").await?;
+                    progress_tx.send_modify(|progress| {
+                        progress.status = "paused".to_string();
+                        progress.eta_secs = None;
+                    });
+                    info!("Envoi de la newsletter {} en pause", newsletter_id);
+                }
+                if control_rx.changed().await.is_err() {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+/// Sleeps until `until`, waking up early if the dispatch is paused or cancelled.
+async fn sleep_until(until: DateTime<Utc>, control_rx: &mut watch::Receiver<Control>) {
+    let delay = (until - Utc::now()).to_std().unwrap_or_default();
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = control_rx.changed() => {}
+    }
+}
+
+/// Holds back the queued deliveries of a newsletter until `retry_at`.
+async fn defer_queued(
+    pool: &SqlitePool,
+    newsletter_id: &str,
+    retry_at: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "update deliveries set next_attempt_at = ?, updated_at = ?
+         where sending_id = ? and status = 'queued' and (next_attempt_at is null or next_attempt_at < ?)",
+    )
+    .bind(retry_at)
+    .bind(Utc::now())
+    .bind(newsletter_id)
+    .bind(retry_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+async fn stop_cancelled(
+    pool: &SqlitePool,
+    newsletter_id: &str,
+    progress_tx: &watch::Sender<SendProgress>,
+) -> Result<(), sqlx::Error> {
+    cancel_queued(pool, newsletter_id).await?;
+    let progress = load_progress(pool, newsletter_id).await?;
+    progress_tx.send_modify(|current| {
+        *current = SendProgress {
+            running: true,
+            ..progress
+        }
+    });
+    info!("Envoi de la newsletter {} annulé", newsletter_id);
+    Ok(())
+}
+
+async fn run(
+    pool: &SqlitePool,
+    newsletter: &NewsletterForSend,
+    progress_tx: &watch::Sender<SendProgress>,
+    mut control_rx: watch::Receiver<Control>,
+) -> Result<(), sqlx::Error> {
+    set_status(pool, &newsletter.id, "sending").await?;
+    let throttle = Throttle::get();
+    let started = Instant::now();
+    let mut processed: u64 = 0;
+    let rendered = render::render_body(newsletter);
+    let sender = Email::get().sender(
+        newsletter.from_name.as_deref(),
+        newsletter.from_email.as_deref(),
+        newsletter.reply_to.as_deref(),
+    );
+
+    loop {
+        let now = Utc::now();
+        let batch = sqlx::query_as::<_, QueuedDelivery>(
+            r#"
+            select d.id, d.email, c.first_name, c.last_name, c.address, c.postal_code, c.city,
+                c.custom_fields, c.unsubscribe_token, coalesce(c.tracking_disabled, 0) as tracking_disabled,
+                d.attempts
+            from deliveries d
+            left join contacts c on c.id = d.contact_id
+            where d.sending_id = ? and d.status = 'queued'
+              and (d.next_attempt_at is null or d.next_attempt_at <= ?)
+            order by d.created_at, d.id
+            limit ?
+            "#,
+        )
+        .bind(&newsletter.id)
+        .bind(now)
+        .bind(BATCH_SIZE)
+        .fetch_all(pool)
+        .await?;
+        let (deferred, next_attempt_at): (i64, Option<DateTime<Utc>>) = sqlx::query_as(
+            "select count(*), min(next_attempt_at) from deliveries
+             where sending_id = ? and status = 'queued' and next_attempt_at > ?",
+        )
+        .bind(&newsletter.id)
+        .bind(now)
+        .fetch_one(pool)
+        .await?;
+        progress_tx.send_modify(|progress| progress.deferred = deferred);
+
+        if batch.is_empty() {
+            let Some(next_attempt_at) = next_attempt_at else {
+                break;
+            };
+            progress_tx.send_modify(|progress| progress.eta_secs = None);
+            sleep_until(next_attempt_at, &mut control_rx).await;
+            if !wait_if_paused(pool, &newsletter.id, &mut control_rx, progress_tx).await? {
+                return stop_cancelled(pool, &newsletter.id, progress_tx).await;
+            }
+            continue;
+        }
+
+        for delivery in &batch {
+            if !wait_if_paused(pool, &newsletter.id, &mut control_rx, progress_tx).await? {
+                return stop_cancelled(pool, &newsletter.id, progress_tx).await;
+            }
+
+            if let Some(retry_at) = throttle.cap_reached(pool).await? {
+                let deferred = defer_queued(pool, &newsletter.id, retry_at).await?;
+                info!(
+                    "Plafond d'envoi atteint, {} remises de la newsletter {} reportées au {}",
+                    deferred, newsletter.id, retry_at
+                );
+                break;
+            }
+            if let Some(retry_at) = throttle.domain_limited(pool, &delivery.email).await? {
+                sqlx::query(
+                    "update deliveries set next_attempt_at = ?, updated_at = ? where id = ?",
+                )
+                .bind(retry_at)
+                .bind(Utc::now())
+                .bind(&delivery.id)
+                .execute(pool)
+                .await?;
+                progress_tx.send_modify(|progress| progress.deferred += 1);
+                continue;
+            }
+            throttle.wait_turn().await;
+
+            let (status, message_id, relay, failure) = match deliver(
+                pool, newsletter, &sender, delivery, &rendered,
+            )
+            .await
+            {
+                Outcome::Sent { message_id, relay } => {
+                    ("sent", Some(message_id), Some(relay.to_string()), None)
+                }
+                Outcome::Suppressed => ("suppressed", None, None, None),
+                Outcome::Rejected(failure) => {
+                    ("bounced", None, failure.relay.clone(), Some(failure))
+                }
+                Outcome::Failed(failure) => ("failed", None, failure.relay.clone(), Some(failure)),
+                Outcome::Halted(failure) => {
+                    error!(
+                        "Envoi de la newsletter {} suspendu, le relais refuse l'envoi: {}",
+                        newsletter.id, failure.message
+                    );
+                    sqlx::query(
+                        "update deliveries set error = ?, smtp_code = ?, relay = ?, updated_at = ? where id = ?",
+                    )
+                    .bind(&failure.message)
+                    .bind(&failure.smtp_code)
+                    .bind(&failure.relay)
+                    .bind(Utc::now())
+                    .bind(&delivery.id)
+                    .execute(pool)
+                    .await?;
+                    send_control(&newsletter.id, Control::Pause);
+                    continue;
+                }
+                Outcome::Retry { failure, retry_at } => {
+                    sqlx::query(
+                        "update deliveries
+                         set attempts = attempts + 1, error = ?, smtp_code = ?, relay = ?, next_attempt_at = ?, updated_at = ?
+                         where id = ?",
+                    )
+                    .bind(&failure.message)
+                    .bind(&failure.smtp_code)
+                    .bind(&failure.relay)
+                    .bind(retry_at)
+                    .bind(Utc::now())
+                    .bind(&delivery.id)
+                    .execute(pool)
+                    .await?;
+                    progress_tx.send_modify(|progress| progress.deferred += 1);
+                    continue;
+                }
+            };
+
+            sqlx::query(
+                "update deliveries
+                 set status = ?, message_id = ?, error = ?, smtp_code = ?, relay = ?, attempts = attempts + ?,
+                     sent_at = ?, next_attempt_at = null, updated_at = ?
+                 where id = ?",
+            )
+            .bind(status)
+            .bind(message_id)
+            .bind(failure.as_ref().map(|failure| &failure.message))
+            .bind(
+                failure
+                    .as_ref()
+                    .and_then(|failure| failure.smtp_code.as_ref()),
+            )
+            .bind(relay)
+            .bind(i64::from(status != "suppressed"))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .bind(&delivery.id)
+            .execute(pool)
+            .await?;
+            if status == "bounced"
+                && let Some(failure) = &failure
+            {
+                record_rejection(pool, delivery, failure).await?;
+            }
+
+            processed += 1;
+            let elapsed = started.elapsed().as_secs_f64().max(0.001);
+            progress_tx.send_modify(|progress| {
+                progress.queued -= 1;
+                match status {
+                    "sent" => progress.sent += 1,
+                    "suppressed" => progress.suppressed += 1,
+                    _ => progress.failed += 1,
+                }
+                let per_second = processed as f64 / elapsed;
+                progress.rate_per_minute = (per_second * 60.0 * 10.0).round() / 10.0;
+                progress.eta_secs =
+                    Some((progress.queued.max(0) as f64 / per_second).ceil() as u64);
+            });
+        }
+    }
+
+    progress_tx.send_modify(|progress| progress.status = "sent".to_string());
+    sqlx::query("update sendings set status = 'sent', sent_at = ?, updated_at = ? where id = ?")
+        .bind(Utc::now())
+        .bind(Utc::now())
+        .bind(&newsletter.id)
+        .execute(pool)
+        .await?;
+
+    let progress = progress_tx.borrow().clone();
+    info!(
+        "Newsletter {} envoyée: {} réussites, {} échecs, {} adresses supprimées",
+        newsletter.id, progress.sent, progress.failed, progress.suppressed
+    );
+    Ok(())
+}
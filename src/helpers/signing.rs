@@ -0,0 +1,62 @@
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::APP_CONFIG;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn secret_key() -> &'static [u8] {
+    APP_CONFIG
+        .get()
+        .expect("Configuration not initialized")
+        .server
+        .secret_key
+        .as_bytes()
+}
+
+/// Hex encoded HMAC-SHA256 of `data` with the given key.
+pub fn sign_with(key: &[u8], data: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Constant-time check of a hex encoded signature produced by `sign_with`.
+pub fn verify_with(key: &[u8], data: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Signs `data` with `server.secret_key`.
+pub fn sign(data: &str) -> String {
+    sign_with(secret_key(), data.as_bytes())
+}
+
+/// Verifies a signature produced by `sign`.
+pub fn verify(data: &str, signature: &str) -> bool {
+    verify_with(secret_key(), data.as_bytes(), signature)
+}
+
+/// Constant-time comparison of a secret received with the expected one, whatever
+/// their lengths: both are reduced to their HMAC before being compared.
+pub fn secret_matches(given: &str, expected: &str) -> bool {
+    verify_with(
+        secret_key(),
+        given.as_bytes(),
+        &sign_with(secret_key(), expected.as_bytes()),
+    )
+}
+
+/// Random alphanumeric token, used where a secret must be stored and looked up.
+pub fn random_token(len: usize) -> String {
+    rand::rng()
+        .sample_iter(&rand::distr::Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
@@ -0,0 +1,349 @@
+use std::cell::RefCell;
+use std::fs;
+use std::sync::{LazyLock, OnceLock};
+
+use lol_html::{RewriteStrSettings, element, text};
+use regex::Regex;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::config::config::SpamConfig;
+use crate::helpers::render::RenderedBody;
+use crate::models::newsletters::NewsletterForSend;
+use crate::models::spam::{SpamReport, SpamRuleHit};
+
+static SPAM_RULES: OnceLock<SpamRules> = OnceLock::new();
+/// Threshold without rules file, SpamAssassin's default.
+const DEFAULT_THRESHOLD: f64 = 5.0;
+
+/// Addresses in plain text content.
+static PLAIN_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)https?://[^\s<>()]+").expect("Invalid URL regex"));
+
+/// Part of the newsletter a rule looks at.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Area {
+    Subject,
+    Body,
+    #[default]
+    All,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum RuleKind {
+    Phrases { phrases: Vec<String> },
+    Capitals { min_ratio: f64, min_letters: usize },
+    ImageRatio { min_text_per_image: usize },
+    Shortener { domains: Vec<String> },
+    LinkMismatch,
+}
+
+#[derive(Deserialize, Debug)]
+struct RuleDefinition {
+    name: String,
+    score: f64,
+    description: String,
+    #[serde(default)]
+    area: Area,
+    #[serde(flatten)]
+    kind: RuleKind,
+}
+
+#[derive(Deserialize, Debug)]
+struct RulesFile {
+    threshold: f64,
+    #[serde(default)]
+    rules: Vec<RuleDefinition>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    definition: RuleDefinition,
+    /// Phrases of a `phrases` rule as one pattern.
+    pattern: Option<Regex>,
+}
+
+/// Text, images and links of the content scored.
+#[derive(Default)]
+struct Content {
+    text: String,
+    images: usize,
+    /// Address and text of each link.
+    links: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub struct SpamRules {
+    threshold: f64,
+    rules: Vec<Rule>,
+}
+
+/// Pattern matching any of `phrases`, ignoring case, as whole words where
+/// they start or end with one.
+fn phrases_pattern(phrases: &[String]) -> Regex {
+    let boundary = |c: Option<char>| {
+        if c.is_some_and(char::is_alphanumeric) {
+            r"\b"
+        } else {
+            ""
+        }
+    };
+    let alternatives: Vec<String> = phrases
+        .iter()
+        .map(|phrase| {
+            format!(
+                "{}{}{}",
+                boundary(phrase.chars().next()),
+                regex::escape(phrase),
+                boundary(phrase.chars().last())
+            )
+        })
+        .collect();
+    Regex::new(&format!("(?i)(?:{})", alternatives.join("|"))).expect("Invalid spam phrases")
+}
+
+/// Host of an address, without `www.`. Addresses without scheme are only
+/// taken when `scheme_optional`, as for the text of a link.
+fn host(url: &str, scheme_optional: bool) -> Option<String> {
+    let url = url.trim().to_lowercase();
+    let rest = match url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+    {
+        Some(rest) => rest,
+        None if scheme_optional => url.as_str(),
+        None => return None,
+    };
+    let host = rest.split(['/', '?', '#', ':']).next()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let is_host = host.contains('.')
+        && !host.starts_with('.')
+        && !host.ends_with('.')
+        && host
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '.' || c == '-');
+    is_host.then(|| host.to_string())
+}
+
+fn extract_html(html: &str) -> Content {
+    let content = RefCell::new(Content::default());
+    let extracted = lol_html::rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                text!("body", |chunk| {
+                    content.borrow_mut().text.push_str(chunk.as_str());
+                    Ok(())
+                }),
+                element!("img", |_| {
+                    content.borrow_mut().images += 1;
+                    Ok(())
+                }),
+                element!("a[href]", |el| {
+                    let href = el.get_attribute("href").unwrap_or_default();
+                    content.borrow_mut().links.push((href, String::new()));
+                    Ok(())
+                }),
+                text!("a[href]", |chunk| {
+                    if let Some((_, text)) = content.borrow_mut().links.last_mut() {
+                        text.push_str(chunk.as_str());
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+    if let Err(e) = extracted {
+        error!("Erreur d'analyse du HTML: {:?}", e);
+    }
+    content.into_inner()
+}
+
+fn extract_plain(text: &str) -> Content {
+    Content {
+        text: text.to_string(),
+        images: 0,
+        links: PLAIN_URL
+            .find_iter(text)
+            .map(|url| (url.as_str().to_string(), String::new()))
+            .collect(),
+    }
+}
+
+impl SpamRules {
+    pub fn init(config: &SpamConfig) {
+        let rules = Self::new(config);
+        SPAM_RULES
+            .set(rules)
+            .expect("SpamRules already initialized");
+    }
+
+    pub fn get() -> &'static SpamRules {
+        SPAM_RULES.get().expect("SpamRules not initialized")
+    }
+
+    pub fn new(config: &SpamConfig) -> Self {
+        let Some(path) = &config.rules_file else {
+            return Self {
+                threshold: DEFAULT_THRESHOLD,
+                rules: Vec::new(),
+            };
+        };
+        let file: RulesFile =
+            toml::from_str(&fs::read_to_string(path).expect("Failed to read spam rules file"))
+                .expect("Invalid spam rules file");
+
+        let rules = file
+            .rules
+            .into_iter()
+            .map(|definition| Rule {
+                pattern: match &definition.kind {
+                    RuleKind::Phrases { phrases } if !phrases.is_empty() => {
+                        Some(phrases_pattern(phrases))
+                    }
+                    _ => None,
+                },
+                definition,
+            })
+            .collect();
+        Self {
+            threshold: file.threshold,
+            rules,
+        }
+    }
+
+    /// Scores the subject and rendered body of a newsletter, before tracking
+    /// rewrites its links.
+    pub fn score(&self, newsletter: &NewsletterForSend, rendered: &RenderedBody) -> SpamReport {
+        let content = if newsletter.content_html.is_some() {
+            extract_html(&rendered.body)
+        } else {
+            extract_plain(&rendered.body)
+        };
+
+        let hits: Vec<SpamRuleHit> = self
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let details = rule.check(&newsletter.subject, &content)?;
+                Some(SpamRuleHit {
+                    name: rule.definition.name.clone(),
+                    score: rule.definition.score,
+                    description: rule.definition.description.clone(),
+                    details,
+                })
+            })
+            .collect();
+        let total = hits.iter().fold(0.0, |total, hit| total + hit.score);
+        let score = (total * 10.0).round() / 10.0;
+        SpamReport {
+            score,
+            threshold: self.threshold,
+            spammy: score >= self.threshold,
+            rules: hits,
+        }
+    }
+}
+
+impl Rule {
+    /// Texts the rule looks at, with how they are named in its details.
+    fn areas<'a>(&self, subject: &'a str, content: &'a Content) -> Vec<(&'static str, &'a str)> {
+        match self.definition.area {
+            Area::Subject => vec![("l'objet", subject)],
+            Area::Body => vec![("le contenu", &content.text)],
+            Area::All => vec![("l'objet", subject), ("le contenu", &content.text)],
+        }
+    }
+
+    /// Details of what matched, `None` when the rule does not apply.
+    fn check(&self, subject: &str, content: &Content) -> Option<String> {
+        match &self.definition.kind {
+            RuleKind::Phrases { .. } => {
+                let pattern = self.pattern.as_ref()?;
+                let found: Vec<String> = self
+                    .areas(subject, content)
+                    .into_iter()
+                    .filter_map(|(area, text)| {
+                        let mut phrases: Vec<String> = Vec::new();
+                        for phrase in pattern.find_iter(text) {
+                            let phrase = phrase.as_str().to_lowercase();
+                            if !phrases.contains(&phrase) {
+                                phrases.push(phrase);
+                            }
+                        }
+                        (!phrases.is_empty())
+                            .then(|| format!("« {} » dans {}", phrases.join(" », « "), area))
+                    })
+                    .collect();
+                (!found.is_empty()).then(|| found.join(", "))
+            }
+            RuleKind::Capitals {
+                min_ratio,
+                min_letters,
+            } => {
+                let found: Vec<String> = self
+                    .areas(subject, content)
+                    .into_iter()
+                    .filter_map(|(area, text)| {
+                        let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+                        let capitals = text.chars().filter(|c| c.is_uppercase()).count();
+                        if letters == 0 || letters < *min_letters {
+                            return None;
+                        }
+                        let ratio = capitals as f64 / letters as f64;
+                        (ratio >= *min_ratio)
+                            .then(|| format!("{:.0} % de majuscules dans {}", ratio * 100.0, area))
+                    })
+                    .collect();
+                (!found.is_empty()).then(|| found.join(", "))
+            }
+            RuleKind::ImageRatio { min_text_per_image } => {
+                if content.images == 0 {
+                    return None;
+                }
+                let characters = content.text.chars().filter(|c| !c.is_whitespace()).count();
+                (characters < content.images * min_text_per_image).then(|| {
+                    format!(
+                        "{} image(s) pour {} caractères de texte",
+                        content.images, characters
+                    )
+                })
+            }
+            RuleKind::Shortener { domains } => {
+                let mut found: Vec<String> = Vec::new();
+                for (href, _) in &content.links {
+                    let Some(host) = host(href, false) else {
+                        continue;
+                    };
+                    let shortened = domains
+                        .iter()
+                        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)));
+                    if shortened && !found.contains(&host) {
+                        found.push(host);
+                    }
+                }
+                (!found.is_empty()).then(|| format!("liens vers {}", found.join(", ")))
+            }
+            RuleKind::LinkMismatch => {
+                let found: Vec<String> = content
+                    .links
+                    .iter()
+                    .filter_map(|(href, text)| {
+                        let text = text.trim();
+                        if text.contains(char::is_whitespace) {
+                            return None;
+                        }
+                        let shown = host(text, true)?;
+                        let target = host(href, false)?;
+                        (shown != target && !target.ends_with(&format!(".{}", shown)))
+                            .then(|| format!("le lien « {} » mène à {}", text, target))
+                    })
+                    .collect();
+                (!found.is_empty()).then(|| found.join(", "))
+            }
+        }
+    }
+}
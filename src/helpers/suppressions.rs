@@ -0,0 +1,129 @@
+use chrono::Utc;
+use sqlx::{Executor, Sqlite, SqlitePool};
+
+pub const SOURCE_MANUAL: &str = "manual";
+pub const SOURCE_IMPORT: &str = "import";
+pub const SOURCE_BOUNCE: &str = "bounce";
+pub const SOURCE_COMPLAINT: &str = "complaint";
+
+const EMAIL_COLUMNS: [&str; 5] = [
+    "email",
+    "email address",
+    "email_address",
+    "e-mail",
+    "address",
+];
+const REASON_COLUMNS: [&str; 3] = ["reason", "status", "type"];
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub async fn is_suppressed(pool: &SqlitePool, email: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query("select 1 from suppressions where email = ?")
+        .bind(normalize_email(email))
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Adds `email` to the suppression list, keeping the first reason recorded.
+/// Returns whether a new entry was created.
+pub async fn suppress<'e, E>(
+    executor: E,
+    email: &str,
+    reason: &str,
+    source: &str,
+) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let result = sqlx::query(
+        "insert or ignore into suppressions (email, reason, source, created_at) values (?, ?, ?, ?)",
+    )
+    .bind(normalize_email(email))
+    .bind(reason)
+    .bind(source)
+    .bind(Utc::now())
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Extracts `(email, reason)` pairs from a suppression export. Accepts plain
+/// one-address-per-line files as well as CSV/TSV exports with a header row
+/// (Mailchimp, Sendgrid, Brevo...), in which case the email and reason
+/// columns are picked by name.
+pub fn parse_suppression_file(content: &str) -> Vec<(String, Option<String>)> {
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    let Some(first) = lines.peek() else {
+        return Vec::new();
+    };
+    let delimiter = [',', ';', '\t']
+        .into_iter()
+        .max_by_key(|d| first.matches(*d).count())
+        .filter(|d| first.contains(*d))
+        .unwrap_or(',');
+
+    let mut email_column = None;
+    let mut reason_column = None;
+    if !first.contains('@') {
+        let header: Vec<String> = split_row(first, delimiter)
+            .into_iter()
+            .map(|h| h.to_lowercase())
+            .collect();
+        email_column = header
+            .iter()
+            .position(|h| EMAIL_COLUMNS.contains(&h.as_str()));
+        reason_column = header
+            .iter()
+            .position(|h| REASON_COLUMNS.contains(&h.as_str()));
+        lines.next();
+    }
+
+    lines
+        .filter_map(|line| {
+            let fields = split_row(line, delimiter);
+            let email = match email_column {
+                Some(index) => fields.get(index).cloned(),
+                None => fields.iter().find(|f| f.contains('@')).cloned(),
+            }?;
+            if !email.contains('@') {
+                return None;
+            }
+            let reason = reason_column
+                .and_then(|index| fields.get(index).cloned())
+                .filter(|r| !r.is_empty());
+            Some((normalize_email(&email), reason))
+        })
+        .collect()
+}
+
+fn split_row(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => {
+                fields.push(current.trim().to_string());
+                current.clear();
+            }
+            c => current.push(c),
+        }
+    }
+    fields.push(current.trim().to_string());
+    fields
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use tokio::time::Instant;
+
+use crate::config::config::ThrottleConfig;
+
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+struct DomainLimits {
+    per_minute: Option<u32>,
+    per_hour: Option<u32>,
+}
+
+/// Sending limits shared by every running dispatch.
+///
+/// The global rate is enforced in memory by handing out send slots; caps and
+/// domain limits are counted on the deliveries sent over rolling windows, so
+/// they hold across restarts. These counts go through the partial indexes on
+/// relayed deliveries rather than scanning the table.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Option<Duration>,
+    hourly_cap: Option<u32>,
+    daily_cap: Option<u32>,
+    domains: HashMap<String, DomainLimits>,
+    next_slot: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn init(config: &ThrottleConfig) {
+        THROTTLE
+            .set(Self::new(config))
+            .expect("Throttle already initialized");
+    }
+
+    pub fn get() -> &'static Throttle {
+        THROTTLE.get().expect("Throttle not initialized")
+    }
+
+    pub fn new(config: &ThrottleConfig) -> Self {
+        Self {
+            interval: config
+                .messages_per_second
+                .map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            hourly_cap: config.hourly_cap,
+            daily_cap: config.daily_cap,
+            domains: config
+                .domains
+                .iter()
+                .map(|(domain, limits)| {
+                    (
+                        domain.to_lowercase(),
+                        DomainLimits {
+                            per_minute: limits.messages_per_minute,
+                            per_hour: limits.messages_per_hour,
+                        },
+                    )
+                })
+                .collect(),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the next send slot of the global rate.
+    pub async fn wait_turn(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let slot = {
+            let mut next_slot = self.next_slot.lock().expect("Throttle lock poisoned");
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// When the hourly or daily cap is reached, the time at which sending can
+    /// go on.
+    pub async fn cap_reached(
+        &self,
+        pool: &SqlitePool,
+    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        let windows = [
+            (self.hourly_cap, chrono::Duration::hours(1)),
+            (self.daily_cap, chrono::Duration::days(1)),
+        ];
+        let mut retry_at = None;
+        for (cap, window) in windows {
+            if let Some(cap) = cap {
+                retry_at = retry_at.max(window_full(pool, None, window, cap).await?);
+            }
+        }
+        Ok(retry_at)
+    }
+
+    /// When the limits of the recipient's domain are reached, the time at
+    /// which it can be sent to again.
+    pub async fn domain_limited(
+        &self,
+        pool: &SqlitePool,
+        email: &str,
+    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        let Some(domain) = recipient_domain(email) else {
+            return Ok(None);
+        };
+        let Some(limits) = self.domains.get(&domain) else {
+            return Ok(None);
+        };
+        let windows = [
+            (limits.per_minute, chrono::Duration::minutes(1)),
+            (limits.per_hour, chrono::Duration::hours(1)),
+        ];
+        let mut retry_at = None;
+        for (limit, window) in windows {
+            if let Some(limit) = limit {
+                retry_at = retry_at.max(window_full(pool, Some(&domain), window, limit).await?);
+            }
+        }
+        Ok(retry_at)
+    }
+}
+
+/// Lowercased domain of `email`, as stored in `deliveries.recipient_domain`.
+pub fn recipient_domain(email: &str) -> Option<String> {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+/// Whether `limit` messages (to `domain`, if given) went out over the last
+/// `window`. If so, returns when enough of them leave the window to send
+/// again.
+async fn window_full(
+    pool: &SqlitePool,
+    domain: Option<&str>,
+    window: chrono::Duration,
+    limit: u32,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let since = Utc::now() - window;
+    let filter = match domain {
+        Some(_) => "message_id is not null and recipient_domain = ? and sent_at >= ?",
+        None => "message_id is not null and sent_at >= ?",
+    };
+
+    let count_sql = format!("select count(*) from deliveries where {}", filter);
+    let mut count = sqlx::query_as::<_, (i64,)>(&count_sql);
+    if let Some(domain) = domain {
+        count = count.bind(domain);
+    }
+    let (count,) = count.bind(since).fetch_one(pool).await?;
+    if count < i64::from(limit) {
+        return Ok(None);
+    }
+
+    // The window has room again once the first `count - limit + 1` sent
+    // messages have left it.
+    let oldest_sql = format!(
+        "select sent_at from deliveries where {} order by sent_at limit 1 offset ?",
+        filter
+    );
+    let mut oldest = sqlx::query_as::<_, (DateTime<Utc>,)>(&oldest_sql);
+    if let Some(domain) = domain {
+        oldest = oldest.bind(domain);
+    }
+    let sent_at = oldest
+        .bind(since)
+        .bind(count - i64::from(limit))
+        .fetch_optional(pool)
+        .await?;
+    Ok(sent_at.map(|(sent_at,)| sent_at + window))
+}
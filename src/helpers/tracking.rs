@@ -0,0 +1,97 @@
+use std::sync::LazyLock;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use regex::{Captures, Regex};
+
+use crate::APP_CONFIG;
+use crate::helpers::signing;
+
+/// `href` attribute of an `<a>` tag, with the quote used around its value.
+static LINK_HREF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)(<a\b[^>]*?\bhref\s*=\s*)(["'])(.*?)(["'])"#).expect("Invalid link regex")
+});
+
+fn tracking_base_url() -> String {
+    let site_url = &APP_CONFIG
+        .get()
+        .expect("Configuration not initialized")
+        .site
+        .site_url;
+    format!("{}/api/t", site_url.trim_end_matches('/'))
+}
+
+/// Signed URL of the 1x1 pixel recording an open of `delivery_id`.
+pub fn open_pixel_url(delivery_id: &str) -> String {
+    let signature = signing::sign(&format!("open:{}", delivery_id));
+    format!("{}/o/{}.{}", tracking_base_url(), delivery_id, signature)
+}
+
+/// Signed URL redirecting to `url` and recording a click of `delivery_id`.
+pub fn click_url(delivery_id: &str, url: &str) -> String {
+    let encoded_url = URL_SAFE_NO_PAD.encode(url);
+    let signature = signing::sign(&format!("click:{}:{}", delivery_id, encoded_url));
+    format!(
+        "{}/c/{}.{}.{}",
+        tracking_base_url(),
+        delivery_id,
+        encoded_url,
+        signature
+    )
+}
+
+/// Checks an open token and returns its delivery id.
+pub fn verify_open_token(token: &str) -> Option<String> {
+    let (delivery_id, signature) = token.split_once('.')?;
+    signing::verify(&format!("open:{}", delivery_id), signature).then(|| delivery_id.to_string())
+}
+
+/// Checks a click token and returns its delivery id and destination URL.
+pub fn verify_click_token(token: &str) -> Option<(String, String)> {
+    let mut parts = token.splitn(3, '.');
+    let (delivery_id, encoded_url, signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if !signing::verify(&format!("click:{}:{}", delivery_id, encoded_url), signature) {
+        return None;
+    }
+    let url = String::from_utf8(URL_SAFE_NO_PAD.decode(encoded_url).ok()?).ok()?;
+    Some((delivery_id.to_string(), url))
+}
+
+/// Rewrites the http(s) links of `html` to the click redirect and/or appends
+/// the open pixel before `</body>`.
+pub fn inject_tracking(html: &str, delivery_id: &str, opens: bool, clicks: bool) -> String {
+    let mut html = if clicks {
+        let tracking_prefix = tracking_base_url();
+        LINK_HREF
+            .replace_all(html, |caps: &Captures| {
+                let url = caps[3].trim().replace("&amp;", "&");
+                let is_web_link = url.starts_with("http://") || url.starts_with("https://");
+                if caps[2] != caps[4] || !is_web_link || url.starts_with(&tracking_prefix) {
+                    return caps[0].to_string();
+                }
+                format!(
+                    "{}{}{}{}",
+                    &caps[1],
+                    &caps[2],
+                    click_url(delivery_id, &url),
+                    &caps[4]
+                )
+            })
+            .into_owned()
+    } else {
+        html.to_string()
+    };
+
+    if opens {
+        let pixel = format!(
+            r#"<img src="{}" width="1" height="1" alt="" style="display:block;border:0;width:1px;height:1px" />"#,
+            open_pixel_url(delivery_id)
+        );
+        match html.to_ascii_lowercase().rfind("</body>") {
+            Some(index) => html.insert_str(index, &pixel),
+            None => html.push_str(&pixel),
+        }
+    }
+
+    html
+}
@@ -0,0 +1,452 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+use sqlx::{Row, SqlitePool};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::config::config::WebhooksConfig;
+use crate::helpers::signing::sign_with;
+
+pub const EVENT_SUBSCRIBED: &str = "contact.subscribed";
+pub const EVENT_UNSUBSCRIBED: &str = "contact.unsubscribed";
+pub const EVENT_BOUNCED: &str = "delivery.bounced";
+pub const EVENT_CLICKED: &str = "delivery.clicked";
+pub const EVENTS: [&str; 4] = [
+    EVENT_SUBSCRIBED,
+    EVENT_UNSUBSCRIBED,
+    EVENT_BOUNCED,
+    EVENT_CLICKED,
+];
+
+/// Deliveries picked per dispatcher pass.
+const BATCH_SIZE: i64 = 50;
+/// Longest wait between two attempts of a delivery.
+const MAX_RETRY_DELAY_SECS: u64 = 6 * 60 * 60;
+/// Response bodies are kept for debugging, truncated to this many bytes.
+const MAX_RESPONSE_BODY: usize = 1024;
+
+/// Queues `event` for every active webhook subscribed to it.
+pub async fn enqueue(pool: &SqlitePool, event: &str, data: Value) -> Result<(), sqlx::Error> {
+    let webhook_ids: Vec<String> = sqlx::query(
+        "select id from webhooks where is_active = 1 and ',' || events || ',' like '%,' || ? || ',%'",
+    )
+    .bind(event)
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|row| row.get("id"))
+    .collect();
+    if webhook_ids.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let payload = json!({
+        "id": Uuid::new_v4().to_string(),
+        "event": event,
+        "created_at": now,
+        "data": data,
+    })
+    .to_string();
+
+    for webhook_id in webhook_ids {
+        sqlx::query(
+            "insert into webhook_deliveries (id, webhook_id, event, payload, status, next_attempt_at, created_at, updated_at)
+             values (?, ?, ?, ?, 'pending', ?, ?, ?)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(&webhook_id)
+        .bind(event)
+        .bind(&payload)
+        .bind(now)
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Same as `enqueue`, for callers where a webhook must never fail the request.
+pub async fn emit(pool: &SqlitePool, event: &str, data: Value) {
+    if let Err(e) = enqueue(pool, event, data).await {
+        error!("Erreur de mise en file du webhook {}: {:?}", event, e);
+    }
+}
+
+/// Fields shared by the `delivery.*` events.
+pub async fn delivery_event_data(
+    pool: &SqlitePool,
+    delivery_id: &str,
+) -> Result<Value, sqlx::Error> {
+    let row = sqlx::query("select sending_id, contact_id, email from deliveries where id = ?")
+        .bind(delivery_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(match row {
+        Some(row) => json!({
+            "delivery_id": delivery_id,
+            "newsletter_id": row.get::<String, _>("sending_id"),
+            "contact_id": row.get::<Option<String>, _>("contact_id"),
+            "email": row.get::<String, _>("email"),
+        }),
+        None => json!({ "delivery_id": delivery_id }),
+    })
+}
+
+#[derive(sqlx::FromRow)]
+struct PendingDelivery {
+    id: String,
+    event: String,
+    payload: String,
+    attempts: i64,
+    url: String,
+    secret: String,
+}
+
+/// Value of the `X-Webhook-Signature` header: HMAC-SHA256 of
+/// `{timestamp}.{body}` keyed with the webhook secret.
+fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    format!(
+        "sha256={}",
+        sign_with(
+            secret.as_bytes(),
+            format!("{}.{}", timestamp, body).as_bytes()
+        )
+    )
+}
+
+fn retry_delay(config: &WebhooksConfig, attempts: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    Duration::from_secs(
+        config
+            .retry_base_secs
+            .saturating_mul(factor)
+            .min(MAX_RETRY_DELAY_SECS),
+    )
+}
+
+async fn attempt(
+    pool: &SqlitePool,
+    client: &reqwest::Client,
+    config: &WebhooksConfig,
+    delivery: PendingDelivery,
+) -> Result<(), sqlx::Error> {
+    let timestamp = Utc::now().timestamp();
+    let result = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", &delivery.id)
+        .header("X-Webhook-Event", &delivery.event)
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "X-Webhook-Signature",
+            signature(&delivery.secret, timestamp, &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+
+    let (response_status, response_body, error) = match result {
+        Ok(response) => {
+            let status = response.status();
+            let mut body = response.text().await.unwrap_or_default();
+            if body.len() > MAX_RESPONSE_BODY {
+                let mut end = MAX_RESPONSE_BODY;
+                while !body.is_char_boundary(end) {
+                    end -= 1;
+                }
+                body.truncate(end);
+            }
+            let error = (!status.is_success()).then(|| format!("HTTP {}", status.as_u16()));
+            (Some(status.as_u16()), Some(body), error)
+        }
+        Err(e) => (None, None, Some(e.to_string())),
+    };
+
+    let attempts = u32::try_from(delivery.attempts)
+        .unwrap_or(u32::MAX)
+        .saturating_add(1);
+    let now = Utc::now();
+    let (status, next_attempt_at): (&str, Option<DateTime<Utc>>) = if error.is_none() {
+        ("delivered", None)
+    } else if attempts >= config.max_attempts {
+        warn!(
+            "Webhook {} abandonné après {} tentatives",
+            delivery.id, attempts
+        );
+        ("failed", None)
+    } else {
+        let delay = retry_delay(config, attempts);
+        (
+            "pending",
+            Some(now + chrono::Duration::from_std(delay).unwrap_or_default()),
+        )
+    };
+
+    sqlx::query(
+        "update webhook_deliveries
+         set status = ?, attempts = ?, next_attempt_at = ?, response_status = ?,
+             response_body = ?, error = ?, updated_at = ?
+         where id = ?",
+    )
+    .bind(status)
+    .bind(attempts)
+    .bind(next_attempt_at)
+    .bind(response_status)
+    .bind(response_body)
+    .bind(error)
+    .bind(now)
+    .bind(&delivery.id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn dispatch_pending(
+    pool: &SqlitePool,
+    client: &reqwest::Client,
+    config: &WebhooksConfig,
+) -> Result<(), sqlx::Error> {
+    let pending = sqlx::query_as::<_, PendingDelivery>(
+        r#"
+        select wd.id, wd.event, wd.payload, wd.attempts, w.url, w.secret
+        from webhook_deliveries wd
+        join webhooks w on w.id = wd.webhook_id
+        where wd.status = 'pending' and w.is_active = 1 and wd.next_attempt_at <= ?
+        order by wd.next_attempt_at
+        limit ?
+        "#,
+    )
+    .bind(Utc::now())
+    .bind(BATCH_SIZE)
+    .fetch_all(pool)
+    .await?;
+
+    if !pending.is_empty() {
+        info!("{} webhook(s) à envoyer", pending.len());
+    }
+    for delivery in pending {
+        attempt(pool, client, config, delivery).await?;
+    }
+    Ok(())
+}
+
+/// Sends queued webhook deliveries in the background, retrying failures with
+/// exponential backoff until `max_attempts` is reached.
+pub fn spawn_dispatcher(pool: SqlitePool, config: &'static WebhooksConfig) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Impossible de créer le client HTTP des webhooks: {:?}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = dispatch_pending(&pool, &client, config).await {
+                error!("Erreur d'envoi des webhooks: {:?}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::Router;
+    use axum::body::{Bytes, to_bytes};
+    use axum::extract::{Path, Query, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::AppState;
+    use crate::handlers::webhooks::list_webhook_deliveries;
+    use crate::helpers::signing::verify_with;
+    use crate::models::webhooks::WebhookDeliveriesQuery;
+
+    const SECRET: &str = "test-secret";
+
+    /// Requests seen by the receiver.
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    async fn test_pool() -> SqlitePool {
+        // A single connection, every connection to `:memory:` being its own database.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Failed to open test database");
+        sqlx::query(include_str!("../../migration/init.sql"))
+            .execute(&pool)
+            .await
+            .expect("Failed to create schema");
+        pool
+    }
+
+    /// Local receiver answering with `statuses` in turn, then 200.
+    async fn spawn_receiver(statuses: Vec<StatusCode>) -> (String, Received) {
+        let received: Received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+        let app = Router::new().route(
+            "/hook",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, body: Bytes| async move {
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((headers, String::from_utf8_lossy(&body).into_owned()));
+                    statuses.lock().unwrap().next().unwrap_or(StatusCode::OK)
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/hook", address), received)
+    }
+
+    async fn create_webhook(pool: &SqlitePool, url: &str) -> String {
+        let id = Uuid::new_v4().to_string();
+        sqlx::query("insert into webhooks (id, url, secret, events) values (?, ?, ?, ?)")
+            .bind(&id)
+            .bind(url)
+            .bind(SECRET)
+            .bind(EVENT_BOUNCED)
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        let config = WebhooksConfig {
+            retry_base_secs: 30,
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (1..=5)
+            .map(|attempts| retry_delay(&config, attempts).as_secs())
+            .collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480]);
+        assert_eq!(retry_delay(&config, 20).as_secs(), MAX_RETRY_DELAY_SECS);
+        assert_eq!(
+            retry_delay(&config, u32::MAX).as_secs(),
+            MAX_RETRY_DELAY_SECS
+        );
+    }
+
+    #[tokio::test]
+    async fn delivery_is_signed() {
+        let pool = test_pool().await;
+        let (url, received) = spawn_receiver(Vec::new()).await;
+        create_webhook(&pool, &url).await;
+        enqueue(&pool, EVENT_BOUNCED, json!({ "email": "a@example.com" }))
+            .await
+            .unwrap();
+
+        dispatch_pending(&pool, &reqwest::Client::new(), &WebhooksConfig::default())
+            .await
+            .unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_eq!(headers["x-webhook-event"], EVENT_BOUNCED);
+        let timestamp = headers["x-webhook-timestamp"].to_str().unwrap();
+        let signature = headers["x-webhook-signature"]
+            .to_str()
+            .unwrap()
+            .strip_prefix("sha256=")
+            .expect("Signature without sha256= prefix");
+        assert!(verify_with(
+            SECRET.as_bytes(),
+            format!("{}.{}", timestamp, body).as_bytes(),
+            signature
+        ));
+        assert!(!verify_with(
+            b"other-secret",
+            format!("{}.{}", timestamp, body).as_bytes(),
+            signature
+        ));
+        let payload: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["event"], EVENT_BOUNCED);
+        assert_eq!(payload["data"]["email"], "a@example.com");
+    }
+
+    #[tokio::test]
+    async fn failed_attempt_is_retried_and_listed() {
+        let pool = test_pool().await;
+        let (url, received) = spawn_receiver(vec![StatusCode::INTERNAL_SERVER_ERROR]).await;
+        let webhook_id = create_webhook(&pool, &url).await;
+        enqueue(&pool, EVENT_BOUNCED, json!({ "email": "a@example.com" }))
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let config = WebhooksConfig::default();
+
+        let before = Utc::now();
+        dispatch_pending(&pool, &client, &config).await.unwrap();
+        let row = sqlx::query(
+            "select status, attempts, response_status, error, next_attempt_at from webhook_deliveries",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(row.get::<String, _>("status"), "pending");
+        assert_eq!(row.get::<i64, _>("attempts"), 1);
+        assert_eq!(row.get::<Option<i64>, _>("response_status"), Some(500));
+        assert_eq!(
+            row.get::<Option<String>, _>("error").as_deref(),
+            Some("HTTP 500")
+        );
+        let next_attempt_at: DateTime<Utc> = row.get("next_attempt_at");
+        let delay = (next_attempt_at - before).num_seconds();
+        assert!((30..=31).contains(&delay), "unexpected delay {}", delay);
+
+        // Not due yet: nothing is sent.
+        dispatch_pending(&pool, &client, &config).await.unwrap();
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        sqlx::query("update webhook_deliveries set next_attempt_at = ?")
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+        dispatch_pending(&pool, &client, &config).await.unwrap();
+        assert_eq!(received.lock().unwrap().len(), 2);
+
+        let response = list_webhook_deliveries(
+            State(AppState {
+                db_pool: pool.clone(),
+            }),
+            Path(webhook_id),
+            Query(WebhookDeliveriesQuery { status: None }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                .unwrap();
+        let deliveries = body["data"].as_array().unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0]["event"], EVENT_BOUNCED);
+        assert_eq!(deliveries[0]["status"], "delivered");
+        assert_eq!(deliveries[0]["attempts"], 2);
+        assert_eq!(deliveries[0]["response_status"], 200);
+        assert_eq!(deliveries[0]["error"], Value::Null);
+        assert_eq!(deliveries[0]["next_attempt_at"], Value::Null);
+    }
+}
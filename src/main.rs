@@ -10,10 +10,21 @@ use args::Args;
 use bcrypt::{DEFAULT_COST, hash};
 use clap::Parser;
 use config::config::Config;
+use helpers::anti_abuse::AntiAbuse;
+use helpers::bounces;
+use helpers::dkim;
 use helpers::email::Email;
+use helpers::signing::random_token;
+use helpers::spam::SpamRules;
+use helpers::throttle::Throttle;
+use helpers::webhooks;
 use rand::Rng;
 use sqlx::SqlitePool;
-use std::{error::Error, sync::OnceLock};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::{error::Error, net::SocketAddr, sync::OnceLock};
 use uuid::Uuid;
 
 static APP_CONFIG: OnceLock<Config> = OnceLock::new();
@@ -53,10 +64,68 @@ async fn init_db(pool: &SqlitePool) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Gives an unsubscribe token to contacts created before they had one.
+async fn backfill_unsubscribe_tokens(pool: &SqlitePool) -> Result<usize, sqlx::Error> {
+    let contacts: Vec<(String,)> =
+        sqlx::query_as("select id from contacts where unsubscribe_token is null")
+            .fetch_all(pool)
+            .await?;
+    let mut tx = pool.begin().await?;
+    for (id,) in &contacts {
+        sqlx::query(
+            "update contacts set unsubscribe_token = ? where id = ? and unsubscribe_token is null",
+        )
+        .bind(random_token(32))
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(contacts.len())
+}
+
+/// Writes a new DKIM private key to `path`, which must not exist yet, and
+/// prints the TXT record publishing its public key.
+fn generate_dkim_key(path: &Path, args: &Args) -> Result<(), Box<dyn Error>> {
+    let domain = args
+        .dkim_domain
+        .as_deref()
+        .ok_or("--dkim-domain is required")?;
+    let (private_key, public_key) = dkim::generate_key(args.dkim_algorithm)?;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(private_key.as_bytes())?;
+
+    println!("DKIM private key written to {}", path.display());
+    println!("Publish this DNS record:");
+    println!(
+        "{}",
+        dkim::dns_record(
+            &args.dkim_selector,
+            domain,
+            args.dkim_algorithm,
+            &public_key
+        )
+    );
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
+    if let Some(path) = &args.generate_dkim_key {
+        if let Err(e) = generate_dkim_key(path, &args) {
+            eprintln!("Failed to generate DKIM key: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let conf = Config::from_file(&args.file_path).expect("Error loading configuration");
 
     APP_CONFIG
@@ -68,6 +137,9 @@ async fn main() {
     telemetry::init_telemetry();
 
     Email::init(&config.email);
+    AntiAbuse::init(&config.anti_abuse);
+    Throttle::init(&config.throttle);
+    SpamRules::init(&config.spam);
 
     let sqlite_db_file_path = &config.database.sqlite.file_path;
 
@@ -96,6 +168,18 @@ async fn main() {
         return;
     }
 
+    match backfill_unsubscribe_tokens(&pool).await {
+        Ok(0) => {}
+        Ok(count) => println!("Unsubscribe tokens generated for {} contacts", count),
+        Err(e) => {
+            eprintln!("Failed to backfill unsubscribe tokens: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    bounces::spawn_maildir_watcher(pool.clone(), &config.bounces);
+    webhooks::spawn_dispatcher(pool.clone(), &config.webhooks);
+
     let state = AppState { db_pool: pool };
 
     let app = routes::create_routes(&state);
@@ -104,5 +188,10 @@ async fn main() {
         .await
         .expect("Failed to bind server address");
     println!("App running on {:?}", listener.local_addr());
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -1,5 +1,7 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::{Validate, ValidationError};
 
 #[derive(Serialize, Deserialize, sqlx::FromRow, Debug)]
 pub struct Contact {
@@ -12,6 +14,8 @@ pub struct Contact {
     pub email: String,
     pub unsubscribe_token: Option<String>,
     pub custom_fields: Option<String>,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub tracking_disabled: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -21,23 +25,71 @@ pub struct ContactListWithMembers {
     pub id: String,
     pub name: String,
     pub list_type: String,
+    pub is_public: bool,
     pub created_at: chrono::DateTime<Utc>,
     pub updated_at: chrono::DateTime<Utc>,
     pub members: Vec<String>,
 }
 
-#[derive(Deserialize, Debug)]
+/// `custom_fields` is stored as a JSON object, sent either as an object or as
+/// its serialized string.
+fn validate_custom_fields(custom_fields: &Value) -> Result<(), ValidationError> {
+    let is_object = match custom_fields {
+        Value::Object(_) => true,
+        Value::String(raw) => matches!(serde_json::from_str(raw), Ok(Value::Object(_))),
+        _ => false,
+    };
+    if is_object {
+        Ok(())
+    } else {
+        Err(ValidationError::new("custom_fields").with_message("Doit être un objet JSON".into()))
+    }
+}
+
+#[derive(Deserialize, Validate, Debug)]
 pub struct NewContactRequest {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub address: Option<String>,
     pub postal_code: Option<String>,
     pub city: Option<String>,
+    #[validate(email(message = "Adresse e-mail invalide"))]
     pub email: String,
-    pub custom_fields: Option<String>,
+    #[validate(custom(function = "validate_custom_fields"))]
+    pub custom_fields: Option<Value>,
+    pub tracking_disabled: Option<bool>,
+}
+
+impl NewContactRequest {
+    /// Serialized `custom_fields`, as stored in the contacts table.
+    pub fn custom_fields_json(&self) -> Option<String> {
+        match &self.custom_fields {
+            Some(Value::String(raw)) => Some(raw.clone()),
+            Some(value) => Some(value.to_string()),
+            None => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContactSyncRequest {
+    #[serde(flatten)]
+    pub contact: NewContactRequest,
+    pub add_lists: Option<Vec<String>>,
+    pub remove_lists: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ContactSyncResponse {
+    pub contact_id: String,
+    pub created: bool,
+    pub added_lists: Vec<String>,
+    pub removed_lists: Vec<String>,
 }
 
 #[derive(Serialize, sqlx::FromRow, Debug)]
 pub struct ContactEmail {
+    pub id: String,
     pub email: String,
+    pub tracking_disabled: bool,
 }
@@ -7,6 +7,7 @@ pub struct ContactList {
     pub name: String,
     #[sqlx(rename = "type")]
     pub list_type: String,
+    pub is_public: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -15,4 +16,5 @@ pub struct ContactList {
 pub struct NewContactListRequest {
     pub name: String,
     pub list_type: String,
+    pub is_public: Option<bool>,
 }
@@ -1,4 +1,15 @@
+pub mod audit;
 pub mod contact;
 pub mod contact_lists;
 pub mod newsletters;
+pub mod preferences;
+pub mod relays;
+pub mod seed_addresses;
+pub mod sender_identities;
+pub mod spam;
+pub mod stats;
+pub mod subscriptions;
+pub mod suppressions;
+pub mod themes;
 pub mod types;
+pub mod webhooks;
@@ -6,10 +6,17 @@ use sqlx::prelude::FromRow;
 pub struct NewsletterRaw {
     pub id: String,
     pub name: String,
+    pub subject: Option<String>,
+    pub preheader: Option<String>,
     pub send_date: Option<DateTime<Utc>>,
     pub status: String,
     pub content_html: Option<String>,
     pub content_plain: Option<String>,
+    pub content_markdown: Option<String>,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    pub theme_id: Option<String>,
+    pub sender_identity_id: Option<String>,
     pub sent_at: Option<DateTime<Utc>>,
     pub sent_by: Option<String>,
     pub created_at: DateTime<Utc>,
@@ -21,10 +28,17 @@ pub struct NewsletterRaw {
 pub struct NewsletterWithLists {
     pub id: String,
     pub name: String,
+    pub subject: Option<String>,
+    pub preheader: Option<String>,
     pub send_date: Option<DateTime<Utc>>,
     pub status: String,
     pub content_html: Option<String>,
     pub content_plain: Option<String>,
+    pub content_markdown: Option<String>,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    pub theme_id: Option<String>,
+    pub sender_identity_id: Option<String>,
     pub sent_at: Option<DateTime<Utc>>,
     pub sent_by: Option<String>,
     pub created_at: DateTime<Utc>,
@@ -35,17 +49,100 @@ pub struct NewsletterWithLists {
 #[derive(Deserialize, Debug)]
 pub struct NewsletterRequest {
     pub name: String,
+    /// Supports merge tags, e.g. `{{ first_name | Bonjour }}`.
+    pub subject: Option<String>,
+    /// Preview text shown by mail clients after the subject.
+    pub preheader: Option<String>,
     pub send_date: Option<String>,
     pub content_type: String,
     pub content: String,
     pub action: String,
     pub contact_list_ids: Option<Vec<String>>,
+    pub track_opens: Option<bool>,
+    pub track_clicks: Option<bool>,
+    /// Header, footer and stylesheet wrapping the content.
+    pub theme_id: Option<String>,
+    /// Sends as the configured identity when unset.
+    pub sender_identity_id: Option<String>,
 }
 
-#[derive(Debug, FromRow)]
+#[derive(Debug, Clone, FromRow)]
 pub struct NewsletterForSend {
     pub id: String,
-    pub name: String,
+    pub subject: String,
+    pub preheader: Option<String>,
     pub content_html: Option<String>,
     pub content_plain: Option<String>,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    pub from_name: Option<String>,
+    pub from_email: Option<String>,
+    pub reply_to: Option<String>,
+    pub theme_header: Option<String>,
+    pub theme_footer: Option<String>,
+    pub theme_stylesheet: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct QueuedDelivery {
+    pub id: String,
+    pub email: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub address: Option<String>,
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    pub custom_fields: Option<String>,
+    pub unsubscribe_token: Option<String>,
+    pub tracking_disabled: bool,
+    pub attempts: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PreviewQuery {
+    pub contact_id: Option<String>,
+    /// `html` (default), `text` or `eml`.
+    pub format: Option<String>,
+}
+
+/// Result of the checks run before a newsletter is sent.
+#[derive(Serialize, Debug, Default)]
+pub struct Preflight {
+    /// Problems that prevent sending.
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TestSendRequest {
+    /// Defaults to `site.admin_emails`.
+    #[serde(default)]
+    pub emails: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestSendResult {
+    pub email: String,
+    pub sent: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct SendProgress {
+    pub newsletter_id: String,
+    /// `sendings.status`: `sending`, `paused`, `cancelled` or `sent`.
+    pub status: String,
+    pub total: i64,
+    pub queued: i64,
+    /// Queued deliveries held back by a throttle limit until a later time.
+    pub deferred: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub suppressed: i64,
+    pub cancelled: i64,
+    /// Messages processed per minute since the dispatch started.
+    pub rate_per_minute: f64,
+    /// Estimated seconds until the queue is empty, once a rate is known.
+    pub eta_secs: Option<u64>,
+    pub running: bool,
 }
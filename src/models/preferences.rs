@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct PreferenceContact {
+    pub id: String,
+    pub email: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub city: Option<String>,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub tracking_disabled: bool,
+}
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct PreferenceList {
+    pub id: String,
+    pub name: String,
+    pub is_public: bool,
+    pub subscribed: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PreferencesResponse {
+    pub email: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub city: Option<String>,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub tracking_disabled: bool,
+    pub lists: Vec<PreferenceList>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdatePreferencesRequest {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub city: Option<String>,
+    pub tracking_disabled: Option<bool>,
+    pub join_lists: Option<Vec<String>>,
+    pub leave_lists: Option<Vec<String>>,
+    /// Number of days to pause emails for, `0` resumes them immediately.
+    #[validate(range(max = 365, message = "La pause ne peut pas dépasser 365 jours"))]
+    pub pause_days: Option<u32>,
+}
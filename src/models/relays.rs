@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default)]
+pub struct RelayStats {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub priority: u32,
+    pub weight: u32,
+    pub healthy: bool,
+    /// End of the cooldown of a relay taken out of rotation.
+    pub down_until: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    /// SMTP attempts and failures since the server started.
+    pub attempts: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Deliveries over the last 24 hours, by final status.
+    pub sent_last_24h: i64,
+    pub failed_last_24h: i64,
+    pub bounced_last_24h: i64,
+}
@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct SeedAddress {
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewSeedAddressRequest {
+    #[validate(email(message = "Adresse e-mail invalide"))]
+    pub email: String,
+}
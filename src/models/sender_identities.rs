@@ -0,0 +1,42 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidateEmail, ValidationError};
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct SenderIdentity {
+    pub id: String,
+    pub from_name: String,
+    pub from_email: String,
+    pub reply_to: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// An empty string removes the Reply-To.
+fn validate_reply_to(reply_to: &str) -> Result<(), ValidationError> {
+    if reply_to.is_empty() || reply_to.validate_email() {
+        Ok(())
+    } else {
+        Err(ValidationError::new("reply_to").with_message("Adresse de réponse invalide".into()))
+    }
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewSenderIdentityRequest {
+    #[validate(length(min = 1, max = 100, message = "Nom d'expéditeur invalide"))]
+    pub from_name: String,
+    #[validate(email(message = "Adresse d'expéditeur invalide"))]
+    pub from_email: String,
+    #[validate(custom(function = "validate_reply_to"))]
+    pub reply_to: Option<String>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateSenderIdentityRequest {
+    #[validate(length(min = 1, max = 100, message = "Nom d'expéditeur invalide"))]
+    pub from_name: Option<String>,
+    #[validate(email(message = "Adresse d'expéditeur invalide"))]
+    pub from_email: Option<String>,
+    #[validate(custom(function = "validate_reply_to"))]
+    pub reply_to: Option<String>,
+}
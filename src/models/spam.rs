@@ -0,0 +1,20 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct SpamReport {
+    pub score: f64,
+    pub threshold: f64,
+    /// Whether the score reaches the threshold.
+    pub spammy: bool,
+    /// Rules the content matched.
+    pub rules: Vec<SpamRuleHit>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SpamRuleHit {
+    pub name: String,
+    pub score: f64,
+    pub description: String,
+    /// What matched, e.g. the phrases found.
+    pub details: String,
+}
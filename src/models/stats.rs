@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct DeliveryCounts {
+    pub sent: i64,
+    pub failed: i64,
+    pub suppressed: i64,
+    pub first_sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct BounceCounts {
+    pub bounced: i64,
+    pub hard: i64,
+    pub soft: i64,
+}
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct EventCounts {
+    pub unique: i64,
+    pub total: i64,
+}
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct LinkClicks {
+    pub url: String,
+    pub unique: i64,
+    pub total: i64,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct SeriesRow {
+    pub kind: String,
+    pub bucket: i64,
+    pub count: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SeriesBucket {
+    pub start: DateTime<Utc>,
+    pub opens: i64,
+    pub clicks: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BounceStats {
+    pub total: i64,
+    pub hard: i64,
+    pub soft: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClickStats {
+    pub unique: i64,
+    pub total: i64,
+    pub links: Vec<LinkClicks>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NewsletterStats {
+    pub sent: i64,
+    pub failed: i64,
+    pub suppressed: i64,
+    pub bounced: BounceStats,
+    pub opens: EventCounts,
+    pub clicks: ClickStats,
+    pub unsubscribes: i64,
+    pub complaints: i64,
+    /// Hourly opens and clicks over the first 48 hours after the first delivery.
+    pub series: Vec<SeriesBucket>,
+}
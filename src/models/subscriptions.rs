@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct SubscribeRequest {
+    #[validate(email(message = "Adresse e-mail invalide"))]
+    pub email: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub list_id: String,
+    /// Honeypot, hidden in the signup form: only bots fill it in.
+    pub website: Option<String>,
+    pub pow_challenge: Option<String>,
+    pub pow_nonce: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChallengeResponse {
+    pub required: bool,
+    pub challenge: String,
+    pub difficulty: u32,
+}
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct Suppression {
+    pub email: String,
+    pub reason: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewSuppressionRequest {
+    #[validate(email(message = "Adresse e-mail invalide"))]
+    pub email: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SuppressionsQuery {
+    pub search: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportSuppressionsQuery {
+    /// Reason used for rows that don't carry their own.
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportSuppressionsResponse {
+    pub imported: u64,
+    pub skipped: u64,
+}
@@ -0,0 +1,44 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct Theme {
+    pub id: String,
+    pub name: String,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    pub stylesheet: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SavedTheme {
+    #[serde(flatten)]
+    pub theme: Theme,
+    /// What the sanitizer took out of the header and footer.
+    pub removed: Vec<String>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewThemeRequest {
+    #[validate(length(min = 1, max = 100, message = "Nom de thème invalide"))]
+    pub name: String,
+    /// HTML placed before the content of the newsletters using the theme.
+    pub header: Option<String>,
+    /// HTML placed after the content.
+    pub footer: Option<String>,
+    /// CSS inlined into the newsletters using the theme.
+    pub stylesheet: Option<String>,
+}
+
+/// An empty string removes the header, footer or stylesheet.
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateThemeRequest {
+    #[validate(length(min = 1, max = 100, message = "Nom de thème invalide"))]
+    pub name: Option<String>,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    pub stylesheet: Option<String>,
+}
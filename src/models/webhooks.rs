@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::helpers::webhooks::EVENTS;
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct WebhookRow {
+    pub id: String,
+    pub url: String,
+    pub events: String,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Self {
+            id: row.id,
+            url: row.url,
+            events: row.events.split(',').map(|e| e.to_string()).collect(),
+            is_active: row.is_active,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+fn validate_events(events: &[String]) -> Result<(), ValidationError> {
+    if events.is_empty() {
+        return Err(ValidationError::new("events").with_message("Au moins un événement".into()));
+    }
+    if events.iter().any(|e| !EVENTS.contains(&e.as_str())) {
+        return Err(ValidationError::new("events")
+            .with_message(format!("Événements acceptés: {}", EVENTS.join(", ")).into()));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct NewWebhookRequest {
+    #[validate(url(message = "URL invalide"))]
+    pub url: String,
+    #[validate(custom(function = "validate_events"))]
+    pub events: Vec<String>,
+    /// Generated when omitted.
+    #[validate(length(min = 16, message = "Le secret doit faire au moins 16 caractères"))]
+    pub secret: Option<String>,
+}
+
+/// Returned once at creation, the secret is not readable afterwards.
+#[derive(Serialize, Debug)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub event: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub response_status: Option<i64>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookDeliveriesQuery {
+    pub status: Option<String>,
+}
+
+#[derive(Deserialize, Validate, Debug)]
+pub struct UpdateWebhookRequest {
+    #[validate(url(message = "URL invalide"))]
+    pub url: Option<String>,
+    #[validate(custom(function = "validate_events"))]
+    pub events: Option<Vec<String>>,
+    pub is_active: Option<bool>,
+}
@@ -1,21 +1,83 @@
+use axum::extract::DefaultBodyLimit;
 use axum::http::StatusCode;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, patch, post};
 use axum::{Router, middleware};
 use serde_json::json;
 
 use crate::AppState;
+use crate::handlers::audit::list_audit_events;
 use crate::handlers::auth::login;
+use crate::handlers::bounces::{ingest_bounce, ingest_complaint};
 use crate::handlers::contact_lists::{
     create_contact, create_contact_list, get_contact_list_by_id, list_contact_lists,
 };
-use crate::handlers::newsletters::{create_newsletter, get_newsletters, send_newsletter};
+use crate::handlers::contact_sync::sync_contact;
+use crate::handlers::newsletters::{
+    cancel_newsletter, create_newsletter, get_newsletters, newsletter_progress,
+    newsletter_spam_score, pause_newsletter, preflight_newsletter, preview_newsletter,
+    resume_newsletter, send_newsletter, test_newsletter,
+};
+use crate::handlers::preferences::{get_preferences, update_preferences};
+use crate::handlers::relays::list_relays;
+use crate::handlers::seed_addresses::{
+    create_seed_address, delete_seed_address, list_seed_addresses,
+};
+use crate::handlers::sender_identities::{
+    create_sender_identity, delete_sender_identity, list_sender_identities, update_sender_identity,
+};
+use crate::handlers::stats::get_newsletter_stats;
+use crate::handlers::subscriptions::{get_challenge, subscribe};
+use crate::handlers::suppressions::{
+    create_suppression, delete_suppression, import_suppressions, list_suppressions,
+};
+use crate::handlers::themes::{create_theme, delete_theme, list_themes, update_theme};
+use crate::handlers::tracking::{track_click, track_open};
+use crate::handlers::webhooks::{
+    create_webhook, delete_webhook, list_webhook_deliveries, list_webhooks, update_webhook,
+};
+use crate::helpers::anti_abuse::{login_rate_limit_middleware, rate_limit_middleware};
+use crate::helpers::audit::audit_middleware;
 use crate::helpers::{auth::auth_middleware, response::response_success};
 use crate::telemetry::request_id_middleware;
 
+const IMPORT_BODY_LIMIT: usize = 20 * 1024 * 1024;
+
 pub fn create_routes(state: &AppState) -> Router {
-    let public_api_routes = Router::new()
+    let login_api_routes = Router::new()
         .route("/login", post(login))
-        .with_state(state.clone());
+        .with_state(state.clone())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit_middleware,
+        ))
+        .layer(middleware::from_fn(login_rate_limit_middleware));
+    let public_api_routes = Router::new()
+        .nest(
+            "/public",
+            Router::new()
+                .route("/challenge", get(get_challenge))
+                .route("/subscribe", post(subscribe))
+                .route("/preferences/{token}", get(get_preferences))
+                .route("/preferences/{token}", patch(update_preferences)),
+        )
+        .with_state(state.clone())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit_middleware,
+        ))
+        .layer(middleware::from_fn(rate_limit_middleware));
+    // Machine-to-machine endpoints, authenticated by their own token or signature.
+    let ingest_api_routes = Router::new()
+        .route("/bounces", post(ingest_bounce))
+        .route("/complaints", post(ingest_complaint))
+        .route("/contacts/sync", post(sync_contact))
+        .route("/t/o/{token}", get(track_open))
+        .route("/t/c/{token}", get(track_click))
+        .with_state(state.clone())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit_middleware,
+        ));
     let private_api_routes = Router::new()
         .route(
             "/ping",
@@ -26,7 +88,16 @@ pub fn create_routes(state: &AppState) -> Router {
             Router::new()
                 .route("/", get(get_newsletters))
                 .route("/", post(create_newsletter))
-                .route("/{id}/send", post(send_newsletter)), // test route
+                .route("/{id}/send", post(send_newsletter)) // test route
+                .route("/{id}/pause", post(pause_newsletter))
+                .route("/{id}/resume", post(resume_newsletter))
+                .route("/{id}/cancel", post(cancel_newsletter))
+                .route("/{id}/progress", get(newsletter_progress))
+                .route("/{id}/stats", get(get_newsletter_stats))
+                .route("/{id}/preview", get(preview_newsletter))
+                .route("/{id}/test", post(test_newsletter))
+                .route("/{id}/preflight", get(preflight_newsletter))
+                .route("/{id}/spam_score", get(newsletter_spam_score)),
         )
         .nest(
             "/contact_lists",
@@ -36,9 +107,65 @@ pub fn create_routes(state: &AppState) -> Router {
                 .route("/{id}", get(get_contact_list_by_id))
                 .route("/{id}/contacts", post(create_contact)),
         )
+        .nest(
+            "/suppressions",
+            Router::new()
+                .route("/", get(list_suppressions))
+                .route("/", post(create_suppression))
+                .route(
+                    "/import",
+                    post(import_suppressions).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+                )
+                .route("/{email}", delete(delete_suppression)),
+        )
+        .nest(
+            "/seed_addresses",
+            Router::new()
+                .route("/", get(list_seed_addresses))
+                .route("/", post(create_seed_address))
+                .route("/{email}", delete(delete_seed_address)),
+        )
+        .nest(
+            "/webhooks",
+            Router::new()
+                .route("/", get(list_webhooks))
+                .route("/", post(create_webhook))
+                .route("/{id}", patch(update_webhook))
+                .route("/{id}", delete(delete_webhook))
+                .route("/{id}/deliveries", get(list_webhook_deliveries)),
+        )
+        .nest(
+            "/sender_identities",
+            Router::new()
+                .route("/", get(list_sender_identities))
+                .route("/", post(create_sender_identity))
+                .route("/{id}", patch(update_sender_identity))
+                .route("/{id}", delete(delete_sender_identity)),
+        )
+        .nest(
+            "/themes",
+            Router::new()
+                .route("/", get(list_themes))
+                .route("/", post(create_theme))
+                .route("/{id}", patch(update_theme))
+                .route("/{id}", delete(delete_theme)),
+        )
+        .route("/relays", get(list_relays))
+        .route("/audit", get(list_audit_events))
         .with_state(state.clone())
-        .layer(middleware::from_fn(auth_middleware))
-        .layer(middleware::from_fn(request_id_middleware));
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit_middleware,
+        ))
+        .layer(middleware::from_fn(auth_middleware));
 
-    Router::new().nest("/api", public_api_routes.merge(private_api_routes))
+    Router::new()
+        .nest(
+            "/api",
+            login_api_routes
+                .merge(public_api_routes)
+                .merge(ingest_api_routes)
+                .merge(private_api_routes),
+        )
+        .layer(middleware::from_fn(request_id_middleware))
 }
@@ -1,4 +1,5 @@
 use axum::extract::Request;
+use axum::http::HeaderValue;
 use axum::middleware::Next;
 use axum::response::Response;
 use opentelemetry::trace::TracerProvider as _;
@@ -42,8 +43,13 @@ pub fn init_telemetry() {
     error!("This event will be logged in the root span.");
 }
 
-pub async fn request_id_middleware(req: Request<axum::body::Body>, next: Next) -> Response {
+/// Id of the current request, also returned in the `x-request-id` header.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+pub async fn request_id_middleware(mut req: Request<axum::body::Body>, next: Next) -> Response {
     let request_id = Uuid::new_v4().to_string();
+    req.extensions_mut().insert(RequestId(request_id.clone()));
     let user_agent = req
         .headers()
         .get("user-agent")
@@ -58,5 +64,9 @@ pub async fn request_id_middleware(req: Request<axum::body::Body>, next: Next) -
         user_agent = %user_agent,
     );
 
-    next.run(req).instrument(span).await
+    let mut response = next.run(req).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
 }